@@ -0,0 +1,159 @@
+//Times a fixed number of steps of some typical scenes, every scene is
+//built from the same seed so runs can be compared with each other,
+//run with cargo bench --no-default-features --bench scenes
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use sandpaint::{MaterialId, MaterialRegistry, SandGrid};
+
+const WIDTH: usize = 400;
+const HEIGHT: usize = 300;
+const SEED: u64 = 1;
+const STEPS: u32 = 60;
+
+type BuildScene = fn(&MaterialRegistry) -> SandGrid;
+
+fn fill_rect(
+    grid: &mut SandGrid,
+    registry: &MaterialRegistry,
+    sand: MaterialId,
+    (x1, y1): (i32, i32),
+    (x2, y2): (i32, i32),
+) {
+    for y in y1..y2 {
+        for x in x1..x2 {
+            grid.place_sand(sand, x, y, 1, registry);
+        }
+    }
+}
+
+//A tall column of sand collapsing into a pile
+fn sand_pile(registry: &MaterialRegistry) -> SandGrid {
+    let mut grid = SandGrid::with_seed(WIDTH, HEIGHT, SEED);
+    fill_rect(&mut grid, registry, MaterialId::SAND, (150, 0), (250, 200));
+    grid
+}
+
+//A block of water spreading out in a walled tank
+fn water_tank(registry: &MaterialRegistry) -> SandGrid {
+    let mut grid = SandGrid::with_seed(WIDTH, HEIGHT, SEED);
+    fill_rect(&mut grid, registry, MaterialId::WALL, (40, 280), (360, 290));
+    fill_rect(&mut grid, registry, MaterialId::WALL, (40, 100), (50, 280));
+    fill_rect(
+        &mut grid,
+        registry,
+        MaterialId::WALL,
+        (350, 100),
+        (360, 280),
+    );
+    fill_rect(
+        &mut grid,
+        registry,
+        MaterialId::WATER,
+        (50, 100),
+        (150, 280),
+    );
+    grid
+}
+
+//A pool of oil burning from one end
+fn oil_fire(registry: &MaterialRegistry) -> SandGrid {
+    let mut grid = SandGrid::with_seed(WIDTH, HEIGHT, SEED);
+    fill_rect(&mut grid, registry, MaterialId::WALL, (40, 280), (360, 290));
+    fill_rect(&mut grid, registry, MaterialId::OIL, (40, 240), (360, 280));
+    fill_rect(&mut grid, registry, MaterialId::FIRE, (40, 240), (50, 250));
+    grid
+}
+
+//A row of explosives set off from one end, every explosion sets off the next
+fn explosive_chain(registry: &MaterialRegistry) -> SandGrid {
+    let mut grid = SandGrid::with_seed(WIDTH, HEIGHT, SEED);
+    fill_rect(&mut grid, registry, MaterialId::WALL, (0, 280), (400, 300));
+    for x in (20..400).step_by(40) {
+        fill_rect(
+            &mut grid,
+            registry,
+            MaterialId::EXPLOSIVE,
+            (x, 270),
+            (x + 10, 280),
+        );
+    }
+    fill_rect(&mut grid, registry, MaterialId::FIRE, (20, 270), (30, 272));
+    grid
+}
+
+//Sand and water that have already come to rest
+fn settled(registry: &MaterialRegistry) -> SandGrid {
+    let mut grid = SandGrid::with_seed(WIDTH, HEIGHT, SEED);
+    fill_rect(&mut grid, registry, MaterialId::WALL, (0, 290), (400, 300));
+    fill_rect(&mut grid, registry, MaterialId::SAND, (0, 230), (400, 290));
+    fill_rect(&mut grid, registry, MaterialId::WATER, (0, 200), (400, 230));
+    for frame in 0..STEPS {
+        grid.update_sand(registry, frame);
+    }
+    grid
+}
+
+fn step_grid(grid: &mut SandGrid, registry: &MaterialRegistry) {
+    for frame in 0..STEPS {
+        grid.update_sand(registry, frame);
+    }
+}
+
+fn count(grid: &SandGrid, sand: MaterialId) -> usize {
+    (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+        .filter(|&(x, y)| grid.get_sand(x, y) == sand)
+        .count()
+}
+
+//The fire in these scenes has to reach its fuel whatever the seed,
+//otherwise they only time a grid that does nothing
+fn check_ignites(registry: &MaterialRegistry) {
+    let scenes: [(&str, BuildScene, MaterialId); 2] = [
+        ("oil_fire", oil_fire, MaterialId::OIL),
+        ("explosive_chain", explosive_chain, MaterialId::EXPLOSIVE),
+    ];
+    for (name, build, fuel) in scenes {
+        for seed in 0..4 {
+            let mut grid = build(registry);
+            grid.set_seed(seed);
+            let before = count(&grid, fuel);
+            step_grid(&mut grid, registry);
+            assert!(
+                count(&grid, fuel) < before,
+                "{name} does not ignite with seed {seed}"
+            );
+        }
+    }
+}
+
+fn scenes(c: &mut Criterion) {
+    let registry = MaterialRegistry::builtin();
+    check_ignites(&registry);
+    let mut group = c.benchmark_group("scenes");
+    group.sample_size(20);
+
+    let scenes: [(&str, BuildScene); 5] = [
+        ("sand_pile", sand_pile),
+        ("water_tank", water_tank),
+        ("oil_fire", oil_fire),
+        ("explosive_chain", explosive_chain),
+        ("settled", settled),
+    ];
+
+    //Every sample starts from a newly built scene
+    for (name, build) in scenes {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || build(&registry),
+                |mut grid| step_grid(&mut grid, &registry),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, scenes);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+//Compares stepping a busy grid on one thread and in parallel chunks,
+//run with cargo bench --no-default-features
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use sandpaint::{MaterialId, MaterialRegistry, SandGrid};
+
+const STEPS: u32 = 30;
+
+//A grid with falling sand, flowing water and a burning block of wood
+fn busy_grid(registry: &MaterialRegistry) -> SandGrid {
+    let mut grid = SandGrid::with_seed(640, 480, 1);
+    grid.place_sand(MaterialId::WALL, 320, 900, 480, registry);
+    for i in 0..6 {
+        grid.place_sand(MaterialId::SAND, 60 + i * 100, 60, 30, registry);
+    }
+    grid.place_sand(MaterialId::WATER, 320, 200, 60, registry);
+    for y in 360..400 {
+        for x in 100..540 {
+            grid.place_sand(MaterialId::WOOD, x, y, 1, registry);
+        }
+    }
+    grid.place_sand(MaterialId::FIRE, 100, 380, 5, registry);
+
+    grid
+}
+
+//Every sample starts from the same grid so the serial and parallel
+//runs do the same amount of work
+fn step_grid(grid: &mut SandGrid, registry: &MaterialRegistry) {
+    for frame in 0..STEPS {
+        grid.update_sand(registry, frame);
+    }
+}
+
+fn step(c: &mut Criterion) {
+    let registry = MaterialRegistry::builtin();
+    let mut group = c.benchmark_group("step");
+    group.sample_size(20);
+
+    for parallel in [false, true] {
+        let name = if parallel { "parallel" } else { "serial" };
+        group.bench_function(BenchmarkId::new(name, "640x480"), |b| {
+            b.iter_batched(
+                || {
+                    let mut grid = busy_grid(&registry);
+                    grid.set_parallel(parallel);
+                    grid
+                },
+                |mut grid| step_grid(&mut grid, &registry),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, step);
+criterion_main!(benches);
@@ -0,0 +1,239 @@
+//Keyboard bindings for the frontend
+//
+//Every action has a default key which can be changed in a TOML file
+//that maps action names to key names, for example:
+//
+//  pause = "Space"
+//  save = "Ctrl+S"
+//  select_1 = "1"
+//
+//Key names are the names SDL gives keys, "Ctrl+" in front of a key
+//means it has to be pressed together with either control key.
+
+use sdl2::keyboard::{Keycode, Mod};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Pause,
+    Step,
+    Clear,
+    Help,
+    Save,
+    Load,
+    ExportImage,
+    ImportImage,
+    Undo,
+    Redo,
+    RewindBack,
+    RewindForward,
+    ToggleParallel,
+    Slower,
+    Faster,
+    BrushSmaller,
+    BrushBigger,
+    Freehand,
+    Line,
+    Rect,
+    Square,
+    Fill,
+    Spray,
+    SprayLess,
+    SprayMore,
+    OnlyAir,
+    OnlyUnderCursor,
+    ProtectUnderCursor,
+    //Index into the material menu
+    SelectMaterial(usize),
+}
+
+//Action, name in the bindings file, default key and what it does
+const ACTIONS: [(Action, &str, &str, &str); 38] = [
+    (Action::Pause, "pause", "Space", "pause"),
+    (Action::Step, "step", ".", "step once while paused"),
+    (Action::Clear, "clear", "Delete", "clear the grid"),
+    (Action::Help, "help", "H", "show these keys"),
+    (Action::Save, "save", "Ctrl+S", "save"),
+    (Action::Load, "load", "Ctrl+O", "load"),
+    (
+        Action::ExportImage,
+        "export_image",
+        "Ctrl+E",
+        "export image",
+    ),
+    (
+        Action::ImportImage,
+        "import_image",
+        "Ctrl+I",
+        "import image",
+    ),
+    (Action::Undo, "undo", "Ctrl+Z", "undo"),
+    (Action::Redo, "redo", "Ctrl+Y", "redo"),
+    (Action::RewindBack, "rewind_back", "Left", "rewind"),
+    (
+        Action::RewindForward,
+        "rewind_forward",
+        "Right",
+        "fast forward",
+    ),
+    (
+        Action::ToggleParallel,
+        "toggle_parallel",
+        "P",
+        "switch threads",
+    ),
+    (Action::Slower, "slower", ",", "slower"),
+    (Action::Faster, "faster", "/", "faster"),
+    (Action::BrushSmaller, "brush_smaller", "[", "smaller brush"),
+    (Action::BrushBigger, "brush_bigger", "]", "bigger brush"),
+    (Action::Freehand, "freehand", "B", "freehand"),
+    (Action::Line, "line", "L", "line"),
+    (Action::Rect, "rect", "R", "rectangle"),
+    (Action::Square, "square", "Q", "square"),
+    (Action::Fill, "fill", "F", "fill"),
+    (Action::Spray, "spray", "A", "spray"),
+    (Action::SprayLess, "spray_less", "-", "thinner spray"),
+    (Action::SprayMore, "spray_more", "=", "thicker spray"),
+    (Action::OnlyAir, "only_air", "N", "only paint air"),
+    (
+        Action::OnlyUnderCursor,
+        "only_under_cursor",
+        "X",
+        "only paint this",
+    ),
+    (
+        Action::ProtectUnderCursor,
+        "protect_under_cursor",
+        "K",
+        "protect this",
+    ),
+    (Action::SelectMaterial(0), "select_1", "1", "material 1"),
+    (Action::SelectMaterial(1), "select_2", "2", "material 2"),
+    (Action::SelectMaterial(2), "select_3", "3", "material 3"),
+    (Action::SelectMaterial(3), "select_4", "4", "material 4"),
+    (Action::SelectMaterial(4), "select_5", "5", "material 5"),
+    (Action::SelectMaterial(5), "select_6", "6", "material 6"),
+    (Action::SelectMaterial(6), "select_7", "7", "material 7"),
+    (Action::SelectMaterial(7), "select_8", "8", "material 8"),
+    (Action::SelectMaterial(8), "select_9", "9", "material 9"),
+    (Action::SelectMaterial(9), "select_10", "0", "material 10"),
+];
+
+impl Action {
+    //Actions that keep happening while their key is held down
+    pub fn repeats(&self) -> bool {
+        matches!(
+            self,
+            Action::Step
+                | Action::Undo
+                | Action::Redo
+                | Action::RewindBack
+                | Action::RewindForward
+                | Action::BrushSmaller
+                | Action::BrushBigger
+                | Action::SprayLess
+                | Action::SprayMore
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Binding {
+    keycode: Keycode,
+    ctrl: bool,
+}
+
+impl Binding {
+    fn parse(name: &str) -> Result<Self, String> {
+        let (ctrl, key) = match name.strip_prefix("Ctrl+") {
+            Some(key) => (true, key),
+            None => (false, name),
+        };
+
+        let keycode = Keycode::from_name(key).ok_or(format!("unknown key {name:?}"))?;
+        Ok(Self { keycode, ctrl })
+    }
+
+    fn name(&self) -> String {
+        if self.ctrl {
+            format!("Ctrl+{}", self.keycode.name())
+        } else {
+            self.keycode.name()
+        }
+    }
+}
+
+pub struct Controls {
+    //Every action with its key, in the order they are shown in the help
+    bindings: Vec<(Action, Binding)>,
+}
+
+impl Controls {
+    //Key names can only be looked up once the SDL video subsystem has started
+    pub fn new() -> Self {
+        Self {
+            bindings: ACTIONS
+                .iter()
+                .map(|(action, _, key, _)| {
+                    (
+                        *action,
+                        Binding::parse(key).expect("default keys are valid"),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    //Keys in the file replace the default keys of their actions,
+    //actions that are not in the file keep their default keys
+    pub fn from_config_str(config: &str) -> Result<Self, String> {
+        let keys: HashMap<String, String> = toml::from_str(config).map_err(|e| e.to_string())?;
+        let mut controls = Self::new();
+
+        for (name, key) in keys {
+            let i = ACTIONS
+                .iter()
+                .position(|(_, action_name, _, _)| *action_name == name)
+                .ok_or(format!("unknown action {name:?}"))?;
+            controls.bindings[i].1 = Binding::parse(&key)?;
+        }
+
+        for (i, (_, binding)) in controls.bindings.iter().enumerate() {
+            if let Some(j) = controls.bindings[..i]
+                .iter()
+                .position(|(_, b)| b == binding)
+            {
+                return Err(format!(
+                    "{} and {} are both bound to {}",
+                    ACTIONS[j].1,
+                    ACTIONS[i].1,
+                    binding.name()
+                ));
+            }
+        }
+
+        Ok(controls)
+    }
+
+    pub fn from_config_file(path: &str) -> Result<Self, String> {
+        let config = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_config_str(&config)
+    }
+
+    pub fn action(&self, keycode: Keycode, keymod: Mod) -> Option<Action> {
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        self.bindings
+            .iter()
+            .find(|(_, binding)| binding.keycode == keycode && binding.ctrl == ctrl)
+            .map(|(action, _)| *action)
+    }
+
+    //Key and description of every action
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        self.bindings
+            .iter()
+            .zip(ACTIONS.iter())
+            .map(|((_, binding), (_, _, _, description))| (binding.name(), *description))
+            .collect()
+    }
+}
@@ -0,0 +1,110 @@
+//A tiny bitmap font for drawing text without any font files,
+//every glyph is 3 pixels wide and 5 pixels tall
+
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+
+//Each row of a glyph is 3 bits, the highest bit is the left pixel
+const GLYPHS: [(char, [u8; 5]); 50] = [
+    ('A', [2, 5, 7, 5, 5]),
+    ('B', [6, 5, 6, 5, 6]),
+    ('C', [3, 4, 4, 4, 3]),
+    ('D', [6, 5, 5, 5, 6]),
+    ('E', [7, 4, 6, 4, 7]),
+    ('F', [7, 4, 6, 4, 4]),
+    ('G', [3, 4, 5, 5, 3]),
+    ('H', [5, 5, 7, 5, 5]),
+    ('I', [7, 2, 2, 2, 7]),
+    ('J', [1, 1, 1, 5, 2]),
+    ('K', [5, 5, 6, 5, 5]),
+    ('L', [4, 4, 4, 4, 7]),
+    ('M', [5, 7, 7, 5, 5]),
+    ('N', [6, 5, 5, 5, 5]),
+    ('O', [2, 5, 5, 5, 2]),
+    ('P', [6, 5, 6, 4, 4]),
+    ('Q', [2, 5, 5, 6, 3]),
+    ('R', [6, 5, 6, 5, 5]),
+    ('S', [3, 4, 2, 1, 6]),
+    ('T', [7, 2, 2, 2, 2]),
+    ('U', [5, 5, 5, 5, 7]),
+    ('V', [5, 5, 5, 5, 2]),
+    ('W', [5, 5, 7, 7, 5]),
+    ('X', [5, 5, 2, 5, 5]),
+    ('Y', [5, 5, 2, 2, 2]),
+    ('Z', [7, 1, 2, 4, 7]),
+    ('0', [7, 5, 5, 5, 7]),
+    ('1', [2, 6, 2, 2, 7]),
+    ('2', [6, 1, 2, 4, 7]),
+    ('3', [6, 1, 2, 1, 6]),
+    ('4', [5, 5, 7, 1, 1]),
+    ('5', [7, 4, 6, 1, 6]),
+    ('6', [3, 4, 6, 5, 2]),
+    ('7', [7, 1, 2, 2, 2]),
+    ('8', [2, 5, 2, 5, 2]),
+    ('9', [2, 5, 3, 1, 6]),
+    (' ', [0, 0, 0, 0, 0]),
+    ('+', [0, 2, 7, 2, 0]),
+    ('-', [0, 0, 7, 0, 0]),
+    ('=', [0, 7, 0, 7, 0]),
+    ('[', [6, 4, 4, 4, 6]),
+    (']', [3, 1, 1, 1, 3]),
+    (',', [0, 0, 0, 2, 4]),
+    ('.', [0, 0, 0, 0, 2]),
+    ('/', [1, 1, 2, 4, 4]),
+    (':', [0, 2, 0, 2, 0]),
+    ('(', [2, 4, 4, 4, 2]),
+    (')', [2, 1, 1, 1, 2]),
+    ('\'', [2, 2, 0, 0, 0]),
+    ('?', [6, 1, 2, 0, 2]),
+];
+
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .find(|(glyph_char, _)| *glyph_char == c)
+        .or(GLYPHS.last())
+        .map(|(_, rows)| *rows)
+        .unwrap_or_default()
+}
+
+//Width in pixels of text drawn at a scale, with a gap of one
+//scaled pixel between glyphs
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * (GLYPH_WIDTH + 1) * scale
+}
+
+//Draws text in the current draw colour with its top left corner at x and y,
+//letters are always drawn in upper case
+pub fn draw_text(
+    canvas: &mut Canvas<Window>,
+    text: &str,
+    x: i32,
+    y: i32,
+    scale: u32,
+) -> Result<(), String> {
+    for (i, c) in text.chars().enumerate() {
+        let glyph_x = x + (i as u32 * (GLYPH_WIDTH + 1) * scale) as i32;
+
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (4 >> column) == 0 {
+                    continue;
+                }
+
+                canvas.fill_rect(Rect::new(
+                    glyph_x + (column * scale) as i32,
+                    y + (row as u32 * scale) as i32,
+                    scale,
+                    scale,
+                ))?;
+            }
+        }
+    }
+
+    Ok(())
+}
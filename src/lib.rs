@@ -0,0 +1,16 @@
+//Headless falling sand simulation, the SDL2 frontend in main.rs
+//is built on top of this library when the `render` feature is enabled
+
+pub mod sand;
+
+pub use sand::{
+    brush::BrushFilter,
+    history::History,
+    material_config::MaterialConfigError,
+    material_registry::{Material, MaterialId, MaterialRegistry},
+    rewind::Rewind,
+    sand_file::SandFileError,
+    sand_image::SandImageError,
+    sand_properties::SandProperties,
+    SandGrid,
+};
@@ -1,25 +1,136 @@
 #![windows_subsystem = "windows"]
 
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseState;
 use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::{Point, Rect};
-use sdl2::render::Canvas;
+use sdl2::render::{BlendMode, Canvas};
 use sdl2::video::Window;
 use sdl2::EventPump;
+use std::path::Path;
 use std::time::Instant;
 
-mod sand;
-use sand::{sand_properties::SandSimulationProperties, Sand, SandGrid};
+mod controls;
+mod font;
+
+use controls::{Action, Controls};
+use sandpaint::sand::{brush, electricity::Charge, sand_properties::MaterialSet};
+use sandpaint::{
+    BrushFilter, History, MaterialId, MaterialRegistry, Rewind, SandFileError, SandGrid,
+};
+
+const SAVE_PATH: &str = "sandpaint.sand";
+//Materials are loaded from this file if it exists,
+//otherwise the built-in materials are used
+const MATERIALS_PATH: &str = "materials.toml";
+const IMAGE_PATH: &str = "sandpaint.png";
+//Key bindings are loaded from this file if it exists,
+//see controls.rs for how it is laid out
+const CONTROLS_PATH: &str = "controls.toml";
+//Maximum distance in RGB space between a pixel and a
+//material colour when importing an image
+const IMPORT_TOLERANCE: u32 = 64;
+//Most memory in bytes that undo history can use before
+//the oldest strokes and snapshots are forgotten
+const UNDO_MEMORY: usize = 64 * 1024 * 1024;
+//The grid is remembered every REWIND_INTERVAL frames so it can be rewound,
+//REWIND_SNAPSHOTS of them covers about 50 seconds at 60 frames a second
+const REWIND_INTERVAL: u32 = 10;
+const REWIND_SNAPSHOTS: usize = 300;
+//Height of the timeline shown at the bottom of the grid while paused
+const TIMELINE_HEIGHT: u32 = 8;
+//Simulation steps a second that the speed can be changed between,
+//the simulation starts at DEFAULT_TICK_RATE of them
+const TICK_RATES: [f64; 9] = [1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 240.0, 480.0, 960.0];
+const DEFAULT_TICK_RATE: usize = 4;
+//Most time spent running steps in one frame when the simulation is behind,
+//if steps take longer than the time between them it would otherwise never
+//catch up
+const MAX_STEP_TIME: f64 = 1.0 / 30.0;
+//How often the steps a second the simulation really runs at are worked out
+//and the average time a step takes is printed
+const MEASURE_INTERVAL: f64 = 0.5;
+//Conductors are drawn in this colour while a spark passes through them
+const SPARK_COLOR: Color = Color::RGB(255, 255, 160);
+
+#[derive(Clone, Copy)]
+enum Tool {
+    Freehand,
+    Line,
+    Rect { filled: bool },
+    Square { filled: bool },
+    //Fills the area of the same material under the cursor
+    Fill,
+    Spray,
+}
+
+impl Tool {
+    fn name(&self) -> &'static str {
+        match self {
+            Tool::Freehand => "freehand",
+            Tool::Line => "line",
+            Tool::Rect { filled: false } => "rectangle outline",
+            Tool::Rect { filled: true } => "filled rectangle",
+            Tool::Square { filled: false } => "square outline",
+            Tool::Square { filled: true } => "filled square",
+            Tool::Fill => "fill",
+            Tool::Spray => "spray",
+        }
+    }
+}
+
+struct ToolState {
+    tool: Tool,
+    radius: u32,
+    //Chance of each cell under the spray getting sand every frame
+    spray_density: f64,
+    //Grid position the left button was pressed at, None while it is up
+    start: Option<(i32, i32)>,
+    //Grid position the tool was last used at
+    last: (i32, i32),
+    //Which materials every tool can paint over
+    filter: BrushFilter,
+    //Sand placed by the current stroke, air when erasing
+    sand: MaterialId,
+}
 
 struct SandSimClock {
     frame: u32,
+    //Time that has passed and not been simulated yet
     timer: f64,
     dt: f64,
+    //Index into TICK_RATES
+    tick_rate: usize,
+    //Time passed since the achieved rate was last worked out, and how
+    //much of it was thrown away instead of simulated
+    measured_time: f64,
+    dropped_time: f64,
+    //Steps taken in that time and how long they took to update
+    measured_steps: u32,
+    update_time: f64,
+    //Steps a second the simulation really ran at, lower than the tick
+    //rate when steps take too long to keep up
+    achieved: f64,
     paused: bool,
     quit: bool,
 }
 
+impl SandSimClock {
+    fn ticks_per_second(&self) -> f64 {
+        TICK_RATES[self.tick_rate]
+    }
+
+    //Starts measuring the achieved rate again, assuming it keeps up
+    //until it has been measured
+    fn restart_measuring(&mut self) {
+        self.measured_time = 0.0;
+        self.dropped_time = 0.0;
+        self.measured_steps = 0;
+        self.update_time = 0.0;
+        self.achieved = self.ticks_per_second();
+    }
+}
+
 //If scroll direction < 0, decrease radius size,
 //if > 0, increase radius size
 fn change_brush_size(
@@ -41,8 +152,9 @@ fn change_brush_size(
 
 fn display_sand_select(
     canvas: &mut Canvas<Window>,
-    sand_menu: &[Sand],
+    sand_menu: &[MaterialId],
     selected_ind: usize,
+    registry: &MaterialRegistry,
 ) -> Result<(), String> {
     let canvas_dimensions = canvas.output_size()?;
     //Display the menu
@@ -51,7 +163,7 @@ fn display_sand_select(
         .fill_rect(Rect::new(0, 0, canvas_dimensions.0, 16))
         .map_err(|e| e.to_string())?;
     for (i, sand) in sand_menu.iter().enumerate() {
-        canvas.set_draw_color(sand::sand_color(*sand));
+        canvas.set_draw_color(registry.sand_color(*sand));
         if i == selected_ind {
             canvas
                 .fill_rect(Rect::new(i as i32 * 16 + 2, 2, 12, 12))
@@ -90,30 +202,202 @@ fn calculate_display_rect(canvas: &Canvas<Window>) -> Rect {
 
 fn update_sand(
     sand_grid: &mut SandGrid,
-    sand_sim_properties: &SandSimulationProperties,
+    registry: &MaterialRegistry,
+    sim_clock: &mut SandSimClock,
+    rewind: &mut Rewind,
+) {
+    if sim_clock.paused {
+        return;
+    }
+
+    //Every step simulates the same amount of time, the time left
+    //over after the last step is carried on to the next frame
+    let tick = 1.0 / sim_clock.ticks_per_second();
+    sim_clock.timer += sim_clock.dt;
+    let start = Instant::now();
+    while sim_clock.timer >= tick {
+        if start.elapsed().as_secs_f64() >= MAX_STEP_TIME {
+            //Too far behind to catch up, slow down instead
+            sim_clock.dropped_time += sim_clock.timer;
+            sim_clock.timer = 0.0;
+            break;
+        }
+
+        step_sand(sand_grid, registry, sim_clock, rewind);
+        sim_clock.timer -= tick;
+    }
+
+    sim_clock.measured_time += sim_clock.dt;
+    if sim_clock.measured_time >= MEASURE_INTERVAL {
+        let simulated = (sim_clock.measured_time - sim_clock.dropped_time).max(0.0);
+        sim_clock.achieved = sim_clock.ticks_per_second() * simulated / sim_clock.measured_time;
+        sim_clock.measured_time = 0.0;
+        sim_clock.dropped_time = 0.0;
+
+        if sim_clock.measured_steps > 0 {
+            println!(
+                "{:.1} ms to update sand on average, {} active chunks",
+                sim_clock.update_time * 1000.0 / sim_clock.measured_steps as f64,
+                sand_grid.active_chunks()
+            );
+        }
+        sim_clock.measured_steps = 0;
+        sim_clock.update_time = 0.0;
+    }
+}
+
+fn change_speed(sim_clock: &mut SandSimClock, faster: bool) {
+    sim_clock.tick_rate = if faster {
+        (sim_clock.tick_rate + 1).min(TICK_RATES.len() - 1)
+    } else {
+        sim_clock.tick_rate.saturating_sub(1)
+    };
+    sim_clock.timer = 0.0;
+    sim_clock.restart_measuring();
+    println!("{} steps a second", sim_clock.ticks_per_second());
+}
+
+//Shows the speed of the simulation at the right of the menu, as steps
+//a second and how much faster or slower than normal that is. When the
+//simulation can not keep up the steps it really runs are shown as well.
+fn display_speed(canvas: &mut Canvas<Window>, sim_clock: &SandSimClock) -> Result<(), String> {
+    let (w, _) = canvas.output_size()?;
+    let ticks_per_second = sim_clock.ticks_per_second();
+    let keeping_up = sim_clock.achieved >= ticks_per_second;
+    let text = if sim_clock.paused {
+        "paused".to_string()
+    } else if !keeping_up {
+        let speed = sim_clock.achieved / TICK_RATES[DEFAULT_TICK_RATE];
+        format!(
+            "{:.0} of {ticks_per_second} steps/s ({speed:.1}x)",
+            sim_clock.achieved
+        )
+    } else {
+        let speed = ticks_per_second / TICK_RATES[DEFAULT_TICK_RATE];
+        if speed >= 1.0 {
+            format!("{ticks_per_second} steps/s ({speed}x)")
+        } else {
+            format!("{ticks_per_second} steps/s (1/{}x)", 1.0 / speed)
+        }
+    };
+
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    font::draw_text(
+        canvas,
+        &text,
+        w as i32 - font::text_width(&text, 2) as i32 - 4,
+        3,
+        2,
+    )
+}
+
+//Updates the simulation once and moves on to the next frame
+fn step_sand(
+    sand_grid: &mut SandGrid,
+    registry: &MaterialRegistry,
     sim_clock: &mut SandSimClock,
+    rewind: &mut Rewind,
 ) {
-    if sim_clock.timer > 1.0 / 60.0 && !sim_clock.paused {
-        let start_sand_update = Instant::now();
+    if let Err(msg) = rewind.record(sim_clock.frame, sand_grid, registry) {
+        println!("failed to remember the grid: {msg}");
+    }
 
-        sand_grid.update_sand(sand_sim_properties, sim_clock.frame);
+    let start_sand_update = Instant::now();
 
-        let time_passed = start_sand_update.elapsed().as_millis();
-        println!("{time_passed} ms to update sand");
+    sand_grid.update_sand(registry, sim_clock.frame);
 
-        sim_clock.timer = 0.0;
-        sim_clock.frame += 1;
+    sim_clock.update_time += start_sand_update.elapsed().as_secs_f64();
+    sim_clock.measured_steps += 1;
+    sim_clock.frame += 1;
+}
+
+//Advancing one step at a time only makes sense while paused
+fn step_once(
+    sand_grid: &mut SandGrid,
+    registry: &MaterialRegistry,
+    sim_clock: &mut SandSimClock,
+    rewind: &mut Rewind,
+) {
+    if sim_clock.paused {
+        step_sand(sand_grid, registry, sim_clock, rewind);
     }
+}
 
-    if !sim_clock.paused {
-        sim_clock.timer += sim_clock.dt;
+//Lists every key binding over the window, in more
+//than one column if they do not fit in one
+fn display_help(canvas: &mut Canvas<Window>, controls: &Controls) -> Result<(), String> {
+    let (w, h) = canvas.output_size()?;
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 192));
+    canvas.fill_rect(Rect::new(0, 16, w, h.saturating_sub(16)))?;
+    canvas.set_blend_mode(BlendMode::None);
+
+    let lines: Vec<String> = controls
+        .help()
+        .iter()
+        .map(|(key, description)| format!("{key:<7} {description}"))
+        .collect();
+    let scale = if h >= 600 { 2 } else { 1 };
+    let line_height = (font::GLYPH_HEIGHT + 2) * scale;
+    let rows = (h.saturating_sub(40) / line_height).max(1) as usize;
+    let column_width = lines
+        .iter()
+        .map(|line| font::text_width(line, scale))
+        .max()
+        .unwrap_or(0)
+        + 8 * scale;
+
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    for (i, line) in lines.iter().enumerate() {
+        let x = 8 + (i / rows) as u32 * column_width;
+        let y = 24 + (i % rows) as u32 * line_height;
+        font::draw_text(canvas, line, x as i32, y as i32, scale)?;
     }
+
+    Ok(())
 }
 
-fn display_sand_grid(pixels: &mut [u8], sand_grid: &SandGrid) {
+fn timeline_rect(display_rect: &Rect) -> Rect {
+    Rect::new(
+        display_rect.x,
+        display_rect.bottom() - TIMELINE_HEIGHT as i32,
+        display_rect.width(),
+        TIMELINE_HEIGHT,
+    )
+}
+
+//Shows how far back the grid can be rewound, the marker is the
+//snapshot being looked at or the end if none has been restored
+fn display_timeline(
+    canvas: &mut Canvas<Window>,
+    display_rect: &Rect,
+    rewind: &Rewind,
+) -> Result<(), String> {
+    if rewind.is_empty() {
+        return Ok(());
+    }
+
+    let timeline = timeline_rect(display_rect);
+    canvas.set_draw_color(Color::RGB(32, 32, 32));
+    canvas.fill_rect(timeline).map_err(|e| e.to_string())?;
+
+    let position = rewind.position().unwrap_or(rewind.len() - 1);
+    let marker_x = timeline.x + (timeline.w - 4) * position as i32 / rewind.len().max(2) as i32;
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas
+        .fill_rect(Rect::new(marker_x, timeline.y, 4, TIMELINE_HEIGHT))
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn display_sand_grid(pixels: &mut [u8], sand_grid: &SandGrid, registry: &MaterialRegistry) {
     for y in 0..sand_grid.height {
         for x in 0..sand_grid.width {
-            let color = sand::sand_color(sand_grid.get_sand(x, y));
+            let color = match sand_grid.get_charge(x, y) {
+                Charge::Spark => SPARK_COLOR,
+                _ => registry.sand_color(sand_grid.get_sand(x, y)),
+            };
             let pixel_pos = sand_grid.width * 4 * y + x * 4;
             pixels[pixel_pos + 1] = color.r;
             pixels[pixel_pos + 2] = color.g;
@@ -122,68 +406,227 @@ fn display_sand_grid(pixels: &mut [u8], sand_grid: &SandGrid) {
     }
 }
 
-fn display_brush(
-    pixels: &mut [u8],
-    mousex: isize,
-    mousey: isize,
-    radius: u32,
+//Darkens the cells the selected tool would place sand in
+fn display_brush(pixels: &mut [u8], cells: &[(i32, i32)], sand_grid: &SandGrid) {
+    for (x, y) in cells.iter().copied() {
+        if sand_grid.out_of_bounds(x as isize, y as isize) {
+            continue;
+        }
+
+        let pixel_pos = sand_grid.width * 4 * y as usize + x as usize * 4;
+        pixels[pixel_pos + 1] /= 4;
+        pixels[pixel_pos + 2] /= 4;
+        pixels[pixel_pos + 3] /= 4;
+        pixels[pixel_pos + 1] *= 3;
+        pixels[pixel_pos + 2] *= 3;
+        pixels[pixel_pos + 3] *= 3;
+    }
+}
+
+fn mouse_grid_position(
+    mouse_state: &MouseState,
+    display_rect: &Rect,
     sand_grid: &SandGrid,
+) -> (i32, i32) {
+    let mousex = ((mouse_state.x() - display_rect.x) as f64 / display_rect.w as f64
+        * sand_grid.width as f64) as i32;
+    let mousey = ((mouse_state.y() - display_rect.y) as f64 / display_rect.h as f64
+        * sand_grid.height as f64) as i32;
+    (mousex, mousey)
+}
+
+fn mouse_over_menu(mouse_state: &MouseState, sand_menu: &[MaterialId]) -> bool {
+    mouse_state.y() < 16 && (mouse_state.x() as usize / 16) < sand_menu.len()
+}
+
+//The cells a tool covers when dragged from one grid position to another,
+//tools that place sand while the button is held only cover the cursor
+fn tool_shape(tools: &ToolState, from: (i32, i32), to: (i32, i32)) -> Vec<(i32, i32)> {
+    match tools.tool {
+        Tool::Freehand | Tool::Spray => brush::circle(to.0, to.1, tools.radius),
+        Tool::Fill => vec![to],
+        Tool::Line => brush::line(from, to, tools.radius),
+        Tool::Rect { filled } => brush::rect(from, to, filled, tools.radius),
+        Tool::Square { filled } => brush::square(from, to, filled, tools.radius),
+    }
+}
+
+//Shows where sand will go, the whole shape is shown while it is being dragged out
+fn tool_preview(tools: &ToolState, position: (i32, i32)) -> Vec<(i32, i32)> {
+    tool_shape(tools, tools.start.unwrap_or(position), position)
+}
+
+//Erasing paints air over everything the filter does not protect
+fn stroke_filter(tools: &ToolState) -> BrushFilter {
+    if tools.sand == MaterialId::AIR {
+        BrushFilter {
+            only: MaterialSet::default(),
+            ..tools.filter
+        }
+    } else {
+        tools.filter
+    }
+}
+
+//Freehand, fill and spray place sand while a mouse button is held
+//down, the other tools place their shape when it is let go. Everything
+//placed while the button is down is one stroke so it is undone at once,
+//and the whole stroke uses the sand it was started with.
+fn use_tool(
+    tools: &mut ToolState,
+    sand_grid: &mut SandGrid,
+    history: &mut History,
+    sand: MaterialId,
+    position: (i32, i32),
+    pressed: bool,
+    registry: &MaterialRegistry,
 ) {
-    for y in (mousey - radius as isize)..(mousey + radius as isize) {
-        for x in (mousex - radius as isize)..(mousex + radius as isize) {
-            if sand_grid.out_of_bounds(x, y) {
-                continue;
+    let cells = match (tools.start, pressed) {
+        (None, false) => return,
+        (None, true) => {
+            tools.start = Some(position);
+            tools.sand = sand;
+            match tools.tool {
+                Tool::Freehand => brush::circle(position.0, position.1, tools.radius),
+                Tool::Fill => brush::flood_fill(sand_grid, position.0, position.1, sand),
+                Tool::Spray => brush::spray(
+                    position.0,
+                    position.1,
+                    tools.radius,
+                    tools.spray_density,
+                    &mut rand::thread_rng(),
+                ),
+                _ => Vec::new(),
             }
+        }
+        (Some(_), true) => match tools.tool {
+            Tool::Freehand => brush::line(tools.last, position, tools.radius),
+            Tool::Spray => brush::spray(
+                position.0,
+                position.1,
+                tools.radius,
+                tools.spray_density,
+                &mut rand::thread_rng(),
+            ),
+            _ => Vec::new(),
+        },
+        (Some(start), false) => {
+            tools.start = None;
+            let cells = match tools.tool {
+                Tool::Line | Tool::Rect { .. } | Tool::Square { .. } => {
+                    tool_shape(tools, start, tools.last)
+                }
+                _ => Vec::new(),
+            };
+            history.place_cells(
+                sand_grid,
+                tools.sand,
+                &cells,
+                &stroke_filter(tools),
+                registry,
+            );
+            history.end_stroke(sand_grid);
+            return;
+        }
+    };
 
-            if !sand::inside_circle(
-                mousex as i32,
-                mousey as i32,
-                radius as i32,
-                x as i32,
-                y as i32,
-            ) {
-                continue;
-            }
+    tools.last = position;
+    history.place_cells(
+        sand_grid,
+        tools.sand,
+        &cells,
+        &stroke_filter(tools),
+        registry,
+    );
+}
 
-            let pixel_pos = sand_grid.width * 4 * y as usize + x as usize * 4;
-            pixels[pixel_pos + 1] /= 4;
-            pixels[pixel_pos + 2] /= 4;
-            pixels[pixel_pos + 3] /= 4;
-            pixels[pixel_pos + 1] *= 3;
-            pixels[pixel_pos + 2] *= 3;
-            pixels[pixel_pos + 3] *= 3;
-        }
+//Pressing the key of the rectangle or square tool again
+//switches between drawing outlines and filled shapes
+fn select_tool(tools: &mut ToolState, tool: Tool) {
+    tools.tool = match (tools.tool, tool) {
+        (Tool::Rect { filled }, Tool::Rect { .. }) => Tool::Rect { filled: !filled },
+        (Tool::Square { filled }, Tool::Square { .. }) => Tool::Square { filled: !filled },
+        _ => tool,
+    };
+    println!("selected {}", tools.tool.name());
+}
+
+//Painting only over one material replaces it with the selected one,
+//choosing the same material again paints over everything
+fn toggle_only(tools: &mut ToolState, sand: MaterialId, registry: &MaterialRegistry) {
+    if tools.filter.only == BrushFilter::only(sand).only {
+        tools.filter.only = MaterialSet::default();
+        println!("painting over every material");
+    } else {
+        tools.filter.only = BrushFilter::only(sand).only;
+        println!("only painting over {}", registry.name(sand));
+    }
+}
+
+fn toggle_protect(tools: &mut ToolState, sand: MaterialId, registry: &MaterialRegistry) {
+    if tools.filter.protect.contains(sand) {
+        tools.filter.protect.remove(sand);
+        println!("no longer protecting {}", registry.name(sand));
+    } else {
+        tools.filter.protect.insert(sand);
+        println!("protecting {}", registry.name(sand));
+    }
+}
+
+fn material_under_mouse(sand_grid: &SandGrid, position: (i32, i32)) -> Option<MaterialId> {
+    if sand_grid.out_of_bounds(position.0 as isize, position.1 as isize) {
+        return None;
     }
+
+    Some(sand_grid.get_sand(position.0 as usize, position.1 as usize))
 }
 
-fn mouse_place_sand(
+fn change_spray_density(tools: &mut ToolState, change: f64) {
+    tools.spray_density = (tools.spray_density + change).clamp(0.01, 1.0);
+    println!("spray density {:.2}", tools.spray_density);
+}
+
+//Clicking the timeline while paused restores the snapshot under the mouse,
+//returns true if the mouse is on the timeline so no sand is placed
+fn mouse_seek_timeline(
     event_pump: &EventPump,
     sand_grid: &mut SandGrid,
-    sand_menu: &[Sand],
-    selected_ind: usize,
-    radius: u32,
+    sim_clock: &mut SandSimClock,
+    rewind: &mut Rewind,
     display_rect: &Rect,
-) {
+    registry: &MaterialRegistry,
+) -> bool {
     let mouse_state = event_pump.mouse_state();
-
-    //Do not place sand if we hovering over the menu
-    let mousex = mouse_state.x() as usize / 16;
-    let mousey = mouse_state.y();
-    if mousey < 16 && mousex < sand_menu.len() {
-        return;
+    let timeline = timeline_rect(display_rect);
+    if !sim_clock.paused
+        || rewind.is_empty()
+        || !timeline.contains_point(Point::new(mouse_state.x(), mouse_state.y()))
+    {
+        return false;
     }
 
-    //Place sand
     if mouse_state.left() {
-        let mousex = ((mouse_state.x() - display_rect.x) as f64 / display_rect.w as f64
-            * sand_grid.width as f64) as i32;
-        let mousey = ((mouse_state.y() - display_rect.y) as f64 / display_rect.h as f64
-            * sand_grid.height as f64) as i32;
-        sand_grid.place_sand(sand_menu[selected_ind], mousex, mousey, radius);
+        let index = ((mouse_state.x() - timeline.x) as usize * rewind.len()
+            / timeline.width() as usize)
+            .min(rewind.len() - 1);
+        if rewind.position() != Some(index) {
+            let restored = rewind.seek(index, sim_clock.frame, sand_grid, registry);
+            restore_frame(sim_clock, restored);
+        }
     }
+
+    true
 }
 
-fn mouse_select_menu(event_pump: &EventPump, sand_menu: &[Sand], selected_ind: usize) -> usize {
+//Left clicking the menu selects a material, middle clicking
+//the grid selects the material under the cursor
+fn mouse_select_menu(
+    event_pump: &EventPump,
+    sand_menu: &[MaterialId],
+    selected_ind: usize,
+    sand_grid: &SandGrid,
+    position: (i32, i32),
+) -> usize {
     let mouse_state = event_pump.mouse_state();
 
     let mousex = mouse_state.x() as usize / 16;
@@ -193,9 +636,171 @@ fn mouse_select_menu(event_pump: &EventPump, sand_menu: &[Sand], selected_ind: u
         return mousex;
     }
 
+    if mouse_state.middle() && !mouse_over_menu(&mouse_state, sand_menu) {
+        //Materials that are not in the menu can not be picked
+        if let Some(ind) = material_under_mouse(sand_grid, position)
+            .and_then(|sand| sand_menu.iter().position(|menu_sand| *menu_sand == sand))
+        {
+            return ind;
+        }
+    }
+
     selected_ind
 }
 
+fn load_controls() -> Result<Controls, String> {
+    if !Path::new(CONTROLS_PATH).exists() {
+        return Ok(Controls::new());
+    }
+
+    println!("loading key bindings from {CONTROLS_PATH}");
+    Controls::from_config_file(CONTROLS_PATH)
+        .map_err(|e| format!("failed to load {CONTROLS_PATH}: {e}"))
+}
+
+fn load_materials() -> Result<MaterialRegistry, String> {
+    if !Path::new(MATERIALS_PATH).exists() {
+        return Ok(MaterialRegistry::builtin());
+    }
+
+    println!("loading materials from {MATERIALS_PATH}");
+    MaterialRegistry::from_config_file(MATERIALS_PATH)
+        .map_err(|e| format!("failed to load {MATERIALS_PATH}: {e}"))
+}
+
+//Every placeable material in the order they were declared,
+//air goes at the end of the menu as the eraser
+fn sand_menu(registry: &MaterialRegistry) -> Vec<MaterialId> {
+    let mut sand_menu: Vec<MaterialId> = registry
+        .placeable()
+        .filter(|id| *id != MaterialId::AIR)
+        .collect();
+    sand_menu.push(MaterialId::AIR);
+    sand_menu
+}
+
+fn save_grid(sand_grid: &SandGrid, registry: &MaterialRegistry) {
+    match sand_grid.save(SAVE_PATH, registry) {
+        Ok(()) => println!("saved to {SAVE_PATH}"),
+        Err(msg) => println!("failed to save {SAVE_PATH}: {msg}"),
+    }
+}
+
+fn load_grid(sand_grid: &mut SandGrid, registry: &MaterialRegistry) {
+    match sand_grid.load_into(SAVE_PATH, registry) {
+        Ok(()) => println!("loaded {SAVE_PATH}"),
+        Err(msg) => println!("failed to load {SAVE_PATH}: {msg}"),
+    }
+}
+
+fn export_image(sand_grid: &SandGrid, registry: &MaterialRegistry) {
+    match sand_grid.export_png(IMAGE_PATH, registry) {
+        Ok(()) => println!("exported {IMAGE_PATH}"),
+        Err(msg) => println!("failed to export {IMAGE_PATH}: {msg}"),
+    }
+}
+
+fn import_image(sand_grid: &mut SandGrid, registry: &MaterialRegistry) {
+    match SandGrid::import_png(IMAGE_PATH, registry, IMPORT_TOLERANCE) {
+        Ok(imported)
+            if imported.width == sand_grid.width && imported.height == sand_grid.height =>
+        {
+            let parallel = sand_grid.is_parallel();
+            *sand_grid = imported;
+            sand_grid.set_parallel(parallel);
+            println!("imported {IMAGE_PATH}");
+        }
+        Ok(imported) => println!(
+            "failed to import {IMAGE_PATH}: expected a {}x{} image, found {}x{}",
+            sand_grid.width, sand_grid.height, imported.width, imported.height
+        ),
+        Err(msg) => println!("failed to import {IMAGE_PATH}: {msg}"),
+    }
+}
+
+//The grid is remembered whenever the simulation is resumed,
+//so undoing can go back to how it was before it ran
+fn toggle_pause(
+    sim_clock: &mut SandSimClock,
+    sand_grid: &SandGrid,
+    history: &mut History,
+    registry: &MaterialRegistry,
+) {
+    sim_clock.paused = !sim_clock.paused;
+    if sim_clock.paused {
+        return;
+    }
+
+    sim_clock.restart_measuring();
+
+    if let Err(msg) = history.snapshot(sand_grid, registry) {
+        println!("failed to remember the grid: {msg}");
+    }
+}
+
+//Carries on the simulation from the frame a snapshot was taken on
+fn restore_frame(sim_clock: &mut SandSimClock, restored: Result<Option<u32>, SandFileError>) {
+    match restored {
+        Ok(Some(frame)) => {
+            sim_clock.frame = frame;
+            println!("rewound to frame {frame}");
+        }
+        Ok(None) => {}
+        Err(msg) => println!("failed to rewind: {msg}"),
+    }
+}
+
+//Stepping through snapshots pauses the simulation, it carries
+//on from the restored snapshot when it is unpaused
+fn rewind_step(
+    sand_grid: &mut SandGrid,
+    sim_clock: &mut SandSimClock,
+    rewind: &mut Rewind,
+    registry: &MaterialRegistry,
+    forward: bool,
+) {
+    sim_clock.paused = true;
+    let restored = if forward {
+        rewind.step_forward(sim_clock.frame, sand_grid, registry)
+    } else {
+        rewind.step_back(sim_clock.frame, sand_grid, registry)
+    };
+    restore_frame(sim_clock, restored);
+}
+
+//Clearing can be undone like a stroke
+fn clear_grid(sand_grid: &mut SandGrid, history: &mut History, registry: &MaterialRegistry) {
+    if let Err(msg) = history.snapshot(sand_grid, registry) {
+        println!("failed to remember the grid: {msg}");
+    }
+    sand_grid.clear();
+}
+
+fn undo(sand_grid: &mut SandGrid, history: &mut History, registry: &MaterialRegistry) {
+    match history.undo(sand_grid, registry) {
+        Ok(true) => {}
+        Ok(false) => println!("nothing to undo"),
+        Err(msg) => println!("failed to undo: {msg}"),
+    }
+}
+
+fn redo(sand_grid: &mut SandGrid, history: &mut History, registry: &MaterialRegistry) {
+    match history.redo(sand_grid, registry) {
+        Ok(true) => {}
+        Ok(false) => println!("nothing to redo"),
+        Err(msg) => println!("failed to redo: {msg}"),
+    }
+}
+
+fn toggle_parallel(sand_grid: &mut SandGrid) {
+    sand_grid.set_parallel(!sand_grid.is_parallel());
+    if sand_grid.is_parallel() {
+        println!("updating sand on multiple threads");
+    } else {
+        println!("updating sand on one thread");
+    }
+}
+
 fn main() -> Result<(), String> {
     const WIDTH: usize = 400;
     const HEIGHT: usize = 300;
@@ -218,6 +823,8 @@ fn main() -> Result<(), String> {
     let mut event_pump = ctx.event_pump().map_err(|e| e.to_string())?;
     let texture_creator = canvas.texture_creator();
     let mut sand_grid = SandGrid::new(WIDTH, HEIGHT);
+    let mut history = History::new(UNDO_MEMORY);
+    let mut rewind = Rewind::new(REWIND_INTERVAL, REWIND_SNAPSHOTS);
     let mut sand_texture = texture_creator
         .create_texture_streaming(
             PixelFormatEnum::BGRA8888,
@@ -227,31 +834,36 @@ fn main() -> Result<(), String> {
         .map_err(|e| e.to_string())?;
 
     let mut selected_sand_ind = 0;
-    let mut radius = 4;
+    let mut tools = ToolState {
+        tool: Tool::Freehand,
+        radius: 4,
+        spray_density: 0.05,
+        start: None,
+        last: (0, 0),
+        filter: BrushFilter::default(),
+        sand: MaterialId::AIR,
+    };
 
     let mut sim_clock = SandSimClock {
         frame: 0,
         timer: 0.0,
         dt: 0.0,
+        tick_rate: DEFAULT_TICK_RATE,
+        measured_time: 0.0,
+        dropped_time: 0.0,
+        measured_steps: 0,
+        update_time: 0.0,
+        achieved: TICK_RATES[DEFAULT_TICK_RATE],
         paused: false,
         quit: false,
     };
 
-    let sand_sim_properties = SandSimulationProperties::simulation_sand_properties();
-
-    let sand_menu = [
-        Sand::Sand,
-        Sand::Water,
-        Sand::Wall,
-        Sand::Wood,
-        Sand::Fire,
-        Sand::Oil,
-        Sand::Acid,
-        Sand::Lava,
-        Sand::Stone,
-        Sand::Explosive,
-        Sand::Air,
-    ];
+    let registry = load_materials()?;
+    let controls = load_controls()?;
+    let mut show_help = false;
+    println!("press H to show the controls");
+
+    let sand_menu = sand_menu(&registry);
 
     while !sim_clock.quit {
         let start = Instant::now();
@@ -259,49 +871,143 @@ fn main() -> Result<(), String> {
         let display_rect = calculate_display_rect(&canvas);
 
         //Update sand simulation
-        update_sand(&mut sand_grid, &sand_sim_properties, &mut sim_clock);
+        update_sand(&mut sand_grid, &registry, &mut sim_clock, &mut rewind);
 
         //Handle mouse events
         let mouse_state = event_pump.mouse_state();
-        selected_sand_ind = mouse_select_menu(&event_pump, &sand_menu, selected_sand_ind);
-        mouse_place_sand(
+        let mouse_position = mouse_grid_position(&mouse_state, &display_rect, &sand_grid);
+        selected_sand_ind = mouse_select_menu(
             &event_pump,
-            &mut sand_grid,
             &sand_menu,
             selected_sand_ind,
-            radius,
-            &display_rect,
+            &sand_grid,
+            mouse_position,
         );
+        if mouse_seek_timeline(
+            &event_pump,
+            &mut sand_grid,
+            &mut sim_clock,
+            &mut rewind,
+            &display_rect,
+            &registry,
+        ) {
+            tools.start = None;
+            history.end_stroke(&sand_grid);
+        } else {
+            use_tool(
+                &mut tools,
+                &mut sand_grid,
+                &mut history,
+                if mouse_state.left() {
+                    sand_menu[selected_sand_ind]
+                } else {
+                    MaterialId::AIR
+                },
+                mouse_position,
+                (mouse_state.left() || mouse_state.right())
+                    && !mouse_over_menu(&mouse_state, &sand_menu),
+                &registry,
+            );
+        }
 
         //Display sand grid
         canvas.set_draw_color(Color::RGB(64, 64, 64));
         canvas.clear();
         sand_texture
             .with_lock(None, |pixels: &mut [u8], _pitch: usize| {
-                display_sand_grid(pixels, &sand_grid);
-                let mousex = ((mouse_state.x() - display_rect.x) as f64 / display_rect.w as f64
-                    * sand_grid.width as f64) as isize;
-                let mousey = ((mouse_state.y() - display_rect.y) as f64 / display_rect.h as f64
-                    * sand_grid.height as f64) as isize;
-                display_brush(pixels, mousex, mousey, radius, &sand_grid);
+                display_sand_grid(pixels, &sand_grid, &registry);
+                display_brush(pixels, &tool_preview(&tools, mouse_position), &sand_grid);
             })
             .map_err(|e| e.to_string())?;
         canvas
             .copy(&sand_texture, None, display_rect)
             .map_err(|e| e.to_string())?;
+        if sim_clock.paused {
+            display_timeline(&mut canvas, &display_rect, &rewind)?;
+        }
+        if show_help {
+            display_help(&mut canvas, &controls)?;
+        }
         //Display Menu
-        display_sand_select(&mut canvas, &sand_menu, selected_sand_ind)
+        display_sand_select(&mut canvas, &sand_menu, selected_sand_ind, &registry)
             .map_err(|e| e.to_string())?;
+        display_speed(&mut canvas, &sim_clock)?;
         canvas.present();
 
         event_pump.poll_iter().for_each(|event| match event {
             Event::Quit { .. } => sim_clock.quit = true,
-            Event::MouseWheel { y, .. } => radius = change_brush_size(radius, 1, 64, y),
+            Event::MouseWheel { y, .. } => tools.radius = change_brush_size(tools.radius, 1, 64, y),
             Event::KeyDown {
-                keycode: Some(Keycode::Space),
-                repeat: false,
+                keycode: Some(keycode),
+                keymod,
+                repeat,
                 ..
-            } => sim_clock.paused = !sim_clock.paused,
+            } => {
+                let Some(action) = controls.action(keycode, keymod) else {
+                    return;
+                };
+                if repeat && !action.repeats() {
+                    return;
+                }
+
+                match action {
+                    Action::Pause => {
+                        toggle_pause(&mut sim_clock, &sand_grid, &mut history, &registry)
+                    }
+                    Action::Step => {
+                        step_once(&mut sand_grid, &registry, &mut sim_clock, &mut rewind)
+                    }
+                    Action::Clear => clear_grid(&mut sand_grid, &mut history, &registry),
+                    Action::Help => show_help = !show_help,
+                    Action::Save => save_grid(&sand_grid, &registry),
+                    Action::Load => load_grid(&mut sand_grid, &registry),
+                    Action::ExportImage => export_image(&sand_grid, &registry),
+                    Action::ImportImage => import_image(&mut sand_grid, &registry),
+                    Action::Undo => undo(&mut sand_grid, &mut history, &registry),
+                    Action::Redo => redo(&mut sand_grid, &mut history, &registry),
+                    Action::RewindBack => rewind_step(
+                        &mut sand_grid,
+                        &mut sim_clock,
+                        &mut rewind,
+                        &registry,
+                        false,
+                    ),
+                    Action::RewindForward => {
+                        rewind_step(&mut sand_grid, &mut sim_clock, &mut rewind, &registry, true)
+                    }
+                    Action::ToggleParallel => toggle_parallel(&mut sand_grid),
+                    Action::Slower => change_speed(&mut sim_clock, false),
+                    Action::Faster => change_speed(&mut sim_clock, true),
+                    Action::BrushSmaller => {
+                        tools.radius = change_brush_size(tools.radius, 1, 64, -1)
+                    }
+                    Action::BrushBigger => tools.radius = change_brush_size(tools.radius, 1, 64, 1),
+                    Action::Freehand => select_tool(&mut tools, Tool::Freehand),
+                    Action::Line => select_tool(&mut tools, Tool::Line),
+                    Action::Rect => select_tool(&mut tools, Tool::Rect { filled: false }),
+                    Action::Square => select_tool(&mut tools, Tool::Square { filled: false }),
+                    Action::Fill => select_tool(&mut tools, Tool::Fill),
+                    Action::Spray => select_tool(&mut tools, Tool::Spray),
+                    Action::SprayLess => change_spray_density(&mut tools, -0.01),
+                    Action::SprayMore => change_spray_density(&mut tools, 0.01),
+                    Action::OnlyAir => toggle_only(&mut tools, MaterialId::AIR, &registry),
+                    Action::OnlyUnderCursor => {
+                        if let Some(sand) = material_under_mouse(&sand_grid, mouse_position) {
+                            toggle_only(&mut tools, sand, &registry)
+                        }
+                    }
+                    Action::ProtectUnderCursor => {
+                        if let Some(sand) = material_under_mouse(&sand_grid, mouse_position) {
+                            toggle_protect(&mut tools, sand, &registry)
+                        }
+                    }
+                    Action::SelectMaterial(ind) => {
+                        if ind < sand_menu.len() {
+                            selected_sand_ind = ind;
+                        }
+                    }
+                }
+            }
             _ => {}
         });
 
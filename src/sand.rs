@@ -1,61 +1,111 @@
-use sdl2::pixels::Color;
-
+use rand::distributions::{Distribution, Standard};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+pub mod brush;
+mod chunks;
+pub mod electricity;
+pub mod history;
+pub mod material_config;
+pub mod material_registry;
+mod parallel;
+pub mod rewind;
+pub mod sand_file;
+pub mod sand_image;
 mod sand_physics;
 pub mod sand_properties;
+pub mod temperature;
 mod update_sand;
 
-use sand_properties::{SandProperties, SandSimulationProperties};
-
-#[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-#[allow(clippy::enum_variant_names)]
-pub enum Sand {
-    Air,
-    Sand,
-    Water,
-    Wall,
-    Wood,
-    Fire,
-    Oil,
-    Acid,
-    Lava,
-    Stone,
-    Explosive,
-    Explosion,
-    OutOfBounds,
-}
+use brush::BrushFilter;
+use chunks::{Chunks, DirtyRect, CHUNK_SIZE};
+use electricity::Charge;
+use material_registry::{MaterialId, MaterialRegistry};
+use sand_properties::{Behavior, Blast, Movement, SandProperties};
 
-#[derive(PartialEq, Clone)]
+//Flags of a particle, packed into one byte
+const UPDATED: u8 = 1;
+const CAN_UPDATE: u8 = 1 << 1;
+const SPARK: u8 = 1 << 2;
+const SPENT: u8 = 1 << 3;
+
+#[derive(PartialEq, Clone, Copy)]
 struct SandParticle {
-    sand_type: Sand,
-    updated: bool,
-    can_update: bool,
+    sand_type: MaterialId,
+    flags: u8,
+}
+
+impl SandParticle {
+    fn new(sand_type: MaterialId, can_update: bool) -> Self {
+        let mut particle = Self {
+            sand_type,
+            flags: 0,
+        };
+        particle.set_can_update(can_update);
+        particle
+    }
+
+    fn updated(&self) -> bool {
+        self.flags & UPDATED != 0
+    }
+
+    fn can_update(&self) -> bool {
+        self.flags & CAN_UPDATE != 0
+    }
+
+    fn set_updated(&mut self, updated: bool) {
+        self.set_flag(UPDATED, updated);
+    }
+
+    fn set_can_update(&mut self, can_update: bool) {
+        self.set_flag(CAN_UPDATE, can_update);
+    }
+
+    fn charge(&self) -> Charge {
+        if self.flags & SPARK != 0 {
+            Charge::Spark
+        } else if self.flags & SPENT != 0 {
+            Charge::Spent
+        } else {
+            Charge::None
+        }
+    }
+
+    fn set_charge(&mut self, charge: Charge) {
+        self.set_flag(SPARK, charge == Charge::Spark);
+        self.set_flag(SPENT, charge == Charge::Spent);
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
 }
 
 pub struct SandGrid {
     grid: Vec<SandParticle>,
+    //All randomness in the simulation comes from this so that
+    //the same seed and grid always produce the same result
+    rng: ChaCha8Rng,
+    temperature: Vec<f32>,
+    //Scratch space for the next temperatures while heat is conducted
+    temperature_buffer: Vec<f32>,
+    //Material each cloner copies, air until something has touched it
+    cloned: Vec<MaterialId>,
+    chunks: Chunks,
+    //Update chunks on several threads at once
+    parallel: bool,
+    //Explosions waiting to happen, only used while updating a chunk on its own
+    deferred_explosions: Option<Vec<(usize, usize, Blast)>>,
+    //Frame being updated, materials that emit use it to count steps
+    frame: u32,
     pub width: usize,
     pub height: usize,
 }
 
-pub fn sand_color(sand: Sand) -> Color {
-    match sand {
-        Sand::Air => Color::WHITE,
-        Sand::Sand => Color::RGB(255, 200, 0),
-        Sand::Water => Color::BLUE,
-        Sand::Wall => Color::GRAY,
-        Sand::Wood => Color::RGB(128, 64, 0),
-        Sand::Fire => Color::RED,
-        Sand::Oil => Color::BLACK,
-        Sand::Acid => Color::GREEN,
-        Sand::Lava => Color::RGB(255, 128, 0),
-        Sand::Stone => Color::RGB(180, 180, 180),
-        Sand::Explosive => Color::RGB(255, 64, 0),
-        Sand::Explosion => Color::RED,
-        _ => Color::WHITE,
-    }
-}
-
 pub fn inside_circle(circle_x: i32, circle_y: i32, radius: i32, x: i32, y: i32) -> bool {
     (circle_y - y) * (circle_y - y) + (circle_x - x) * (circle_x - x) < (radius * radius)
 }
@@ -65,21 +115,54 @@ impl SandGrid {
         x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize
     }
 
+    //Creates a grid with a random seed
     pub fn new(w: usize, h: usize) -> Self {
+        Self::with_seed(w, h, rand::random())
+    }
+
+    pub fn with_seed(w: usize, h: usize, seed: u64) -> Self {
+        let grid = vec![SandParticle::new(MaterialId::AIR, false); w * h];
+
+        Self::from_particles(w, h, grid, seed)
+    }
+
+    fn from_particles(w: usize, h: usize, grid: Vec<SandParticle>, seed: u64) -> Self {
         SandGrid {
-            grid: vec![
-                SandParticle {
-                    sand_type: Sand::Air,
-                    updated: false,
-                    can_update: false
-                };
-                w * h
-            ],
+            temperature: vec![temperature::AMBIENT_TEMPERATURE; grid.len()],
+            temperature_buffer: Vec::new(),
+            cloned: vec![MaterialId::AIR; grid.len()],
+            chunks: Chunks::new(w, h),
+            parallel: false,
+            deferred_explosions: None,
+            frame: 0,
+            grid,
+            rng: ChaCha8Rng::seed_from_u64(seed),
             width: w,
             height: h,
         }
     }
 
+    //Fills the grid with air at the ambient temperature
+    pub fn clear(&mut self) {
+        self.grid.fill(SandParticle::new(MaterialId::AIR, false));
+        self.temperature.fill(temperature::AMBIENT_TEMPERATURE);
+        self.cloned.fill(MaterialId::AIR);
+        self.chunks = Chunks::new(self.width, self.height);
+    }
+
+    //Restarts the random number generator, stepping two grids with the
+    //same contents after seeding them the same way gives identical results
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    pub(crate) fn random<T>(&mut self) -> T
+    where
+        Standard: Distribution<T>,
+    {
+        self.rng.gen()
+    }
+
     fn set_adjacent_can_update(&mut self, x: usize, y: usize) {
         const ADJ_X: [isize; 8] = [0, 0, -1, 1, -1, -1, 1, 1];
         const ADJ_Y: [isize; 8] = [-1, 1, 0, 0, -1, 1, -1, 1];
@@ -94,17 +177,36 @@ impl SandGrid {
         }
     }
 
+    //Only looks in the directions the material can move in, so particles
+    //resting on the ground stop updating even when there is air above them
     fn check_space_nearby(&self, x: usize, y: usize, sand_property: &SandProperties) -> bool {
-        const ADJ_X: [isize; 8] = [0, 0, -1, 1, -1, -1, 1, 1];
-        const ADJ_Y: [isize; 8] = [-1, 1, 0, 0, -1, 1, -1, 1];
+        let directions: &[(isize, isize)] = match sand_property.movement {
+            Movement::Static => &[],
+            Movement::Powder => &[(0, 1), (-1, 1), (1, 1)],
+            Movement::Fall => &[(0, 1)],
+            Movement::Liquid => &[(0, 1), (-1, 1), (1, 1), (-1, 0), (1, 0)],
+            Movement::Gas => &[(0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+        };
 
-        for i in 0..8 {
-            let posx = (x as isize + ADJ_X[i]) as usize;
-            let posy = (y as isize + ADJ_Y[i]) as usize;
-            if self.space_available(posx, posy, sand_property)
-                || sand_property
-                    .can_sink_in
-                    .contains(&self.get_sand(posx, posy))
+        for (dx, dy) in directions {
+            let posx = x as isize + dx;
+            let posy = y as isize + dy;
+            if self.out_of_bounds(posx, posy) {
+                continue;
+            }
+
+            let (posx, posy) = (posx as usize, posy as usize);
+            let sand = self.get_sand(posx, posy);
+            let displace = if *dy < 0 {
+                &sand_property.rise_into
+            } else {
+                &sand_property.sink_into
+            };
+            //Powders resting on a pile steeper than they slide off can sleep
+            let slides =
+                *dx == 0 || *dy < 0 || sand_physics::can_slide(y, posx, self, sand_property);
+            if (self.space_available(posx, posy, sand_property) && slides)
+                || displace.contains_key(sand)
             {
                 return true;
             }
@@ -114,32 +216,69 @@ impl SandGrid {
     }
 
     //Place sand in a circle centered at posx and posy
-    pub fn place_sand(&mut self, sand: Sand, posx: i32, posy: i32, radius: u32) {
-        for y in (posy - radius as i32)..(posy + radius as i32) {
-            for x in (posx - radius as i32)..(posx + radius as i32) {
-                if self.out_of_bounds(x as isize, y as isize) {
-                    continue;
-                }
+    pub fn place_sand(
+        &mut self,
+        sand: MaterialId,
+        posx: i32,
+        posy: i32,
+        radius: u32,
+        registry: &MaterialRegistry,
+    ) {
+        self.place_cells(
+            sand,
+            &brush::circle(posx, posy, radius),
+            &BrushFilter::default(),
+            registry,
+        );
+    }
 
-                if inside_circle(posx, posy, radius as i32, x, y) {
-                    self.set_sand(x as usize, y as usize, sand);
-                    self.grid[y as usize * self.width + x as usize].can_update = true;
-                    self.grid[y as usize * self.width + x as usize].updated = false;
-                    self.set_adjacent_can_update(x as usize, y as usize);
-                }
+    //Place sand in every cell of a shape made with the brush module that
+    //the filter allows painting over, cells outside the grid are skipped
+    pub fn place_cells(
+        &mut self,
+        sand: MaterialId,
+        cells: &[(i32, i32)],
+        filter: &BrushFilter,
+        registry: &MaterialRegistry,
+    ) {
+        let temperature = registry.initial_temperature(sand);
+
+        for (x, y) in cells.iter().copied() {
+            if self.out_of_bounds(x as isize, y as isize)
+                || !filter.allows(self.get_sand(x as usize, y as usize))
+            {
+                continue;
             }
+
+            self.set_sand(x as usize, y as usize, sand);
+            self.set_temperature(x as usize, y as usize, temperature);
+            self.cloned[y as usize * self.width + x as usize] = MaterialId::AIR;
+            self.grid[y as usize * self.width + x as usize].set_updated(false);
+            self.set_can_update(x as usize, y as usize);
+            self.set_adjacent_can_update(x as usize, y as usize);
+            self.chunks.mark_charged(x as usize, y as usize);
         }
     }
 
-    pub fn get_sand(&self, x: usize, y: usize) -> Sand {
+    pub fn get_sand(&self, x: usize, y: usize) -> MaterialId {
         if self.out_of_bounds(x as isize, y as isize) {
-            return Sand::OutOfBounds;
+            return MaterialId::OUT_OF_BOUNDS;
         }
 
         self.grid[y * self.width + x].sand_type
     }
 
-    pub fn set_sand(&mut self, x: usize, y: usize, sand: Sand) {
+    //Material the cloner at the position copies, air
+    //when it has not been touched by anything yet
+    pub fn get_cloned(&self, x: usize, y: usize) -> MaterialId {
+        if self.out_of_bounds(x as isize, y as isize) {
+            return MaterialId::AIR;
+        }
+
+        self.cloned[y * self.width + x]
+    }
+
+    pub fn set_sand(&mut self, x: usize, y: usize, sand: MaterialId) {
         if self.out_of_bounds(x as isize, y as isize) {
             return;
         }
@@ -152,7 +291,8 @@ impl SandGrid {
             return;
         }
 
-        self.grid[y * self.width + x].updated = true;
+        self.grid[y * self.width + x].set_updated(true);
+        self.chunks.mark_dirty(x, y);
     }
 
     pub fn set_can_update(&mut self, x: usize, y: usize) {
@@ -160,7 +300,8 @@ impl SandGrid {
             return;
         }
 
-        self.grid[y * self.width + x].can_update = true;
+        self.grid[y * self.width + x].set_can_update(true);
+        self.chunks.mark_dirty(x, y);
     }
 
     pub fn get_updated(&self, x: usize, y: usize) -> bool {
@@ -168,7 +309,7 @@ impl SandGrid {
             return false;
         }
 
-        self.grid[y * self.width + x].updated
+        self.grid[y * self.width + x].updated()
     }
 
     pub fn space_available(&self, x: usize, y: usize, properties: &SandProperties) -> bool {
@@ -176,41 +317,95 @@ impl SandGrid {
             return false;
         }
 
-        properties.can_replace.contains(&self.get_sand(x, y))
-            && !self.grid[y * self.width + x].updated
+        properties.can_replace.contains(self.get_sand(x, y))
+            && !self.grid[y * self.width + x].updated()
     }
 
-    fn invert_x_on_even(&self, x: usize, frame: u32) -> usize {
-        if frame % 2 == 0 {
-            return self.width - 1 - x;
+    //Number of chunks with cells that can update
+    pub fn active_chunks(&self) -> usize {
+        self.chunks
+            .dirty
+            .iter()
+            .filter(|rect| !rect.is_empty())
+            .count()
+    }
+
+    pub fn update_sand(&mut self, registry: &MaterialRegistry, frame: u32) {
+        self.frame = frame;
+
+        if self.parallel {
+            self.update_pixels_parallel(registry, frame);
+        } else {
+            self.update_pixels(registry, frame);
         }
 
-        x
+        self.update_temperature(registry);
+        self.update_electricity(registry);
+
+        //Every cell that can update or was updated is inside a dirty rect,
+        //the rects are rebuilt from the cells that can still update
+        let dirty = std::mem::replace(
+            &mut self.chunks.dirty,
+            vec![DirtyRect::EMPTY; self.chunks.columns * self.chunks.rows],
+        );
+        for rect in dirty.into_iter().filter(|rect| !rect.is_empty()) {
+            for y in rect.min_y..=rect.max_y {
+                for x in rect.min_x..=rect.max_x {
+                    let i = y * self.width + x;
+
+                    if self.grid[i].updated() || self.grid[i].sand_type == MaterialId::FIRE {
+                        self.set_can_update(x, y);
+                        self.set_adjacent_can_update(x, y);
+                    }
+
+                    self.grid[i].set_updated(false);
+
+                    //Sparks carried by moving liquids have to be found again
+                    if self.grid[i].charge() != Charge::None {
+                        self.chunks.mark_charged(x, y);
+                    }
+
+                    if self.grid[i].can_update() {
+                        self.chunks.mark_dirty(x, y);
+                    }
+                }
+            }
+        }
     }
 
-    pub fn update_sand(&mut self, sand_sim_properties: &SandSimulationProperties, frame: u32) {
-        //Update the sand grid
+    //Update the sand grid, only the dirty part of each chunk is visited
+    //but the cells are still visited row by row and the x direction is
+    //reversed on even frames
+    fn update_pixels(&mut self, registry: &MaterialRegistry, frame: u32) {
+        let reverse = frame.is_multiple_of(2);
         for y in 0..self.height {
-            for xval in 0..self.width {
-                let x = self.invert_x_on_even(xval, frame);
-                self.update_pixel(x, y, sand_sim_properties);
-            }
-        }
+            let row = y / CHUNK_SIZE;
+            for i in 0..self.chunks.columns {
+                let column = if reverse {
+                    self.chunks.columns - 1 - i
+                } else {
+                    i
+                };
 
-        for i in 0..self.grid.len() {
-            let (x, y) = (i % self.width, i / self.width);
+                let rect = self.chunks.dirty[row * self.chunks.columns + column];
+                if !rect.contains_row(y) {
+                    continue;
+                }
 
-            if self.grid[i].updated || self.grid[i].sand_type == Sand::Fire {
-                self.set_can_update(x, y);
-                self.set_adjacent_can_update(x, y);
+                for xval in rect.min_x..=rect.max_x {
+                    let x = if reverse {
+                        rect.max_x + rect.min_x - xval
+                    } else {
+                        xval
+                    };
+                    self.update_pixel(x, y, registry);
+                }
             }
-
-            self.grid[i].updated = false;
         }
     }
 
-    fn update_pixel(&mut self, x: usize, y: usize, sand_sim_properties: &SandSimulationProperties) {
-        if !self.grid[self.width * y + x].can_update {
+    fn update_pixel(&mut self, x: usize, y: usize, registry: &MaterialRegistry) {
+        if !self.grid[self.width * y + x].can_update() {
             return;
         }
 
@@ -218,74 +413,174 @@ impl SandGrid {
             return;
         }
 
-        if self.get_sand(x, y) == Sand::Air {
+        //Air never does anything so it stops updating
+        //straight away and lets its chunk settle down
+        if self.get_sand(x, y) == MaterialId::AIR {
+            self.grid[self.width * y + x].set_can_update(false);
             return;
         }
 
-        let sand_property_op = sand_sim_properties.get_sand_property(self.get_sand(x, y));
+        let sand_property_op = registry.get_sand_property(self.get_sand(x, y));
         let sand_property = match sand_property_op {
             Some(sand_prop) => sand_prop,
-            _ => return,
+            _ => {
+                self.grid[self.width * y + x].set_can_update(false);
+                return;
+            }
         };
 
         let sand = self.get_sand(x, y);
 
-        match sand {
-            Sand::Sand => {
-                update_sand::update_particle(x, y, self, sand_property);
-            }
-            Sand::Water => {
-                update_sand::update_liquid(x, y, self, sand_property);
-            }
-            Sand::Oil => {
-                update_sand::transform_from_neighbors(
-                    x,
-                    y,
-                    Sand::Lava,
-                    Sand::Fire,
-                    self,
-                    1,
-                    4,
-                    0.2,
-                );
-                update_sand::update_liquid(x, y, self, sand_property);
-            }
-            Sand::Lava => {
-                update_sand::update_liquid(x, y, self, sand_property);
-            }
-            Sand::Acid => {
-                update_sand::update_liquid(x, y, self, sand_property);
-            }
-            Sand::Fire => {
+        match sand_property.behavior {
+            Behavior::Fire => {
                 update_sand::update_fire(x, y, self, sand_property);
             }
-            Sand::Stone => {
-                sand_physics::fall_down(x, y, self, sand_property);
+            Behavior::Explosive => {
+                update_sand::update_explosive(x, y, self, &sand_property.blast, registry);
             }
-            Sand::Wood => {
-                update_sand::transform_from_neighbors(
-                    x,
-                    y,
-                    Sand::Lava,
-                    Sand::Fire,
-                    self,
-                    1,
-                    4,
-                    0.2,
-                );
+            Behavior::Cloner => {
+                update_sand::update_cloner(x, y, self, registry);
             }
-            Sand::Explosive => {
-                update_sand::update_explosive(x, y, self, sand_property, sand_sim_properties);
+            //Particles delete themselves when they move into a void
+            Behavior::Void | Behavior::None => {}
+        }
+
+        if let Some(emit) = &sand_property.emit {
+            update_sand::update_emit(x, y, self, emit, registry);
+        }
+
+        if let Some(burn) = &sand_property.burn {
+            update_sand::update_burn(x, y, self, burn, registry);
+        }
+
+        if let Some(decay) = &sand_property.decay {
+            update_sand::update_decay(x, y, self, decay, registry);
+        }
+
+        for transform in &sand_property.transforms {
+            update_sand::transform_from_neighbors(x, y, self, transform);
+        }
+
+        if let Some(germination) = &sand_property.germination {
+            update_sand::update_germination(x, y, self, germination, registry);
+        }
+
+        if let Some(growth) = &sand_property.growth {
+            update_sand::update_growth(x, y, self, growth, registry);
+        }
+
+        if self.get_sand(x, y) == sand
+            && update_sand::enters_void(x, y, self, sand_property.movement, registry)
+        {
+            self.set_sand(x, y, MaterialId::AIR);
+            self.set_updated(x, y);
+        } else if self.get_sand(x, y) == sand {
+            match sand_property.movement {
+                Movement::Powder => {
+                    update_sand::update_particle(x, y, self, sand_property);
+                }
+                Movement::Fall => {
+                    sand_physics::fall_down(x, y, self, sand_property);
+                }
+                Movement::Liquid => {
+                    update_sand::update_liquid(x, y, self, sand_property);
+                }
+                Movement::Gas => {
+                    update_sand::update_gas(x, y, self, sand_property);
+                }
+                Movement::Static => {}
             }
-            _ => {}
         }
 
         if self.get_sand(x, y) != sand {
-            self.update_pixel(x, y, sand_sim_properties);
+            self.update_pixel(x, y, registry);
         }
 
-        if !self.check_space_nearby(x, y, sand_property) && !self.get_updated(x, y) {
-            self.grid[self.width * y + x].can_update = false;
+        //Materials that decay, transform, germinate, grow, emit or delete
+        //particles have to keep updating even when they can not move
+        if !self.check_space_nearby(x, y, sand_property)
+            && !self.get_updated(x, y)
+            && sand_property.decay.is_none()
+            && !sand_property
+                .transforms
+                .iter()
+                .any(|transform| update_sand::can_transform(x, y, self, transform))
+            && !sand_property
+                .germination
+                .is_some_and(|germination| update_sand::can_germinate(x, y, self, &germination))
+            && !sand_property
+                .growth
+                .is_some_and(|growth| update_sand::can_grow(x, y, self, &growth))
+            && !update_sand::source_or_sink_active(x, y, self, sand_property, registry)
+        {
+            self.grid[self.width * y + x].set_can_update(false);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A grid spanning several chunks with most kinds of behavior in it,
+    //stepped long enough for fire, explosions and sparks to run their course
+    fn run(seed: u64, parallel: bool) -> SandGrid {
+        let registry = MaterialRegistry::builtin();
+        let id = |name: &str| registry.id(name).unwrap();
+        let mut grid = SandGrid::with_seed(64, 64, seed);
+        grid.set_parallel(parallel);
+
+        let filter = BrushFilter::default();
+        let row = |y| (0..64).map(|x| (x, y)).collect::<Vec<_>>();
+        grid.place_cells(MaterialId::WALL, &row(63), &filter, &registry);
+        grid.place_cells(id("Wire"), &row(40), &filter, &registry);
+        grid.place_cells(id("Battery"), &[(0, 39)], &filter, &registry);
+        grid.place_cells(id("Cloner"), &[(21, 30)], &filter, &registry);
+        grid.place_sand(MaterialId::WOOD, 50, 39, 2, &registry);
+        grid.place_sand(MaterialId::OIL, 15, 20, 5, &registry);
+        grid.place_sand(MaterialId::FIRE, 15, 14, 2, &registry);
+        grid.place_sand(id("Gunpowder"), 54, 58, 3, &registry);
+        grid.place_sand(MaterialId::LAVA, 54, 50, 2, &registry);
+
+        for frame in 0..150 {
+            if frame < 50 {
+                grid.place_sand(MaterialId::SAND, 20, 3, 2, &registry);
+                grid.place_sand(MaterialId::WATER, 50, 3, 2, &registry);
+            }
+            grid.update_sand(&registry, frame);
+        }
+
+        grid
+    }
+
+    fn assert_same(a: &SandGrid, b: &SandGrid) {
+        assert!(a.grid == b.grid);
+        assert_eq!(
+            a.temperature
+                .iter()
+                .map(|t| t.to_bits())
+                .collect::<Vec<_>>(),
+            b.temperature
+                .iter()
+                .map(|t| t.to_bits())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(a.cloned, b.cloned);
+    }
+
+    //Otherwise the tests below could pass without the rng being used
+    #[test]
+    fn seed_changes_result() {
+        assert!(run(7, false).grid != run(8, false).grid);
+    }
+
+    #[test]
+    fn deterministic_serial() {
+        assert_same(&run(7, false), &run(7, false));
+    }
+
+    #[test]
+    fn deterministic_parallel() {
+        assert_same(&run(7, true), &run(7, true));
+    }
+}
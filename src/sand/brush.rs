@@ -0,0 +1,348 @@
+//Shapes that can be drawn on a grid, each one is the list of cells it
+//covers so they can all be placed with SandGrid::place_cells. Cells
+//can be outside the grid, those are skipped when placing.
+
+use super::{inside_circle, material_registry::MaterialId, sand_properties::MaterialSet, SandGrid};
+use rand::Rng;
+
+//Decides which cells under a brush get painted, the default
+//paints over everything
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct BrushFilter {
+    //Only cells holding one of these materials are painted,
+    //every material can be painted over when it is empty
+    pub only: MaterialSet,
+    //Cells holding these materials are never painted over
+    pub protect: MaterialSet,
+}
+
+impl BrushFilter {
+    //Only paints over cells holding sand
+    pub fn only(sand: MaterialId) -> Self {
+        let mut filter = Self::default();
+        filter.only.insert(sand);
+        filter
+    }
+
+    //Only paints into empty space
+    pub fn air_only() -> Self {
+        Self::only(MaterialId::AIR)
+    }
+
+    //Never paints over cells holding sand
+    pub fn protect(sand: MaterialId) -> Self {
+        let mut filter = Self::default();
+        filter.protect.insert(sand);
+        filter
+    }
+
+    pub fn allows(&self, sand: MaterialId) -> bool {
+        (self.only.is_empty() || self.only.contains(sand)) && !self.protect.contains(sand)
+    }
+}
+
+//Filled circle centered at x and y
+pub fn circle(x: i32, y: i32, radius: u32) -> Vec<(i32, i32)> {
+    let radius = radius as i32;
+    let mut cells = Vec::new();
+
+    for posy in (y - radius)..(y + radius) {
+        for posx in (x - radius)..(x + radius) {
+            if inside_circle(x, y, radius, posx, posy) {
+                cells.push((posx, posy));
+            }
+        }
+    }
+
+    cells
+}
+
+//Circles stamped at every point on the line between from and to,
+//so a fast moving brush does not leave gaps
+pub fn line(from: (i32, i32), to: (i32, i32), radius: u32) -> Vec<(i32, i32)> {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let step_x = if x < to.0 { 1 } else { -1 };
+    let step_y = if y < to.1 { 1 } else { -1 };
+    let mut error = dx + dy;
+    let mut cells = Vec::new();
+
+    loop {
+        cells.extend(circle(x, y, radius));
+        if (x, y) == to {
+            break;
+        }
+
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+
+    cells
+}
+
+//Rectangle with corners at a and b, the outline is drawn with lines
+//of the given radius
+pub fn rect(a: (i32, i32), b: (i32, i32), filled: bool, radius: u32) -> Vec<(i32, i32)> {
+    let (min_x, max_x) = (a.0.min(b.0), a.0.max(b.0));
+    let (min_y, max_y) = (a.1.min(b.1), a.1.max(b.1));
+
+    if filled {
+        return (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+            .collect();
+    }
+
+    let mut cells = line((min_x, min_y), (max_x, min_y), radius);
+    cells.extend(line((max_x, min_y), (max_x, max_y), radius));
+    cells.extend(line((max_x, max_y), (min_x, max_y), radius));
+    cells.extend(line((min_x, max_y), (min_x, min_y), radius));
+    cells
+}
+
+//Rectangle from a towards b with equal sides, as big as the
+//longest side of the rectangle between a and b
+pub fn square(a: (i32, i32), b: (i32, i32), filled: bool, radius: u32) -> Vec<(i32, i32)> {
+    let side = (b.0 - a.0).abs().max((b.1 - a.1).abs());
+    let direction = |from: i32, to: i32| if to < from { -1 } else { 1 };
+    let corner = (
+        a.0 + side * direction(a.0, b.0),
+        a.1 + side * direction(a.1, b.1),
+    );
+    rect(a, corner, filled, radius)
+}
+
+//Every cell in the circle has a chance of density to be picked
+pub fn spray<R: Rng>(x: i32, y: i32, radius: u32, density: f64, rng: &mut R) -> Vec<(i32, i32)> {
+    circle(x, y, radius)
+        .into_iter()
+        .filter(|_| rng.gen::<f64>() < density)
+        .collect()
+}
+
+//Cells connected to x and y by their sides that hold the same
+//material, nothing is filled if that material is already sand
+pub fn flood_fill(grid: &SandGrid, x: i32, y: i32, sand: MaterialId) -> Vec<(i32, i32)> {
+    if grid.out_of_bounds(x as isize, y as isize) {
+        return Vec::new();
+    }
+
+    let target = grid.get_sand(x as usize, y as usize);
+    if target == sand {
+        return Vec::new();
+    }
+
+    let mut visited = vec![false; grid.width * grid.height];
+    let mut stack = vec![(x, y)];
+    let mut cells = Vec::new();
+    visited[y as usize * grid.width + x as usize] = true;
+
+    while let Some((x, y)) = stack.pop() {
+        cells.push((x, y));
+
+        for (posx, posy) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            if grid.out_of_bounds(posx as isize, posy as isize) {
+                continue;
+            }
+
+            let i = posy as usize * grid.width + posx as usize;
+            if !visited[i] && grid.get_sand(posx as usize, posy as usize) == target {
+                visited[i] = true;
+                stack.push((posx, posy));
+            }
+        }
+    }
+
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sand::material_registry::MaterialRegistry;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use std::collections::HashSet;
+
+    fn set(cells: &[(i32, i32)]) -> HashSet<(i32, i32)> {
+        cells.iter().copied().collect()
+    }
+
+    #[test]
+    fn circle_footprint() {
+        assert!(circle(5, 5, 0).is_empty());
+        assert_eq!(circle(5, 5, 1), vec![(5, 5)]);
+        let block: Vec<(i32, i32)> = (4..=6).flat_map(|y| (4..=6).map(move |x| (x, y))).collect();
+        assert_eq!(circle(5, 5, 2), block);
+
+        //Every cell closer to the center than the radius, each once
+        let cells = circle(5, 5, 6);
+        assert_eq!(set(&cells).len(), cells.len());
+        let inside: Vec<(i32, i32)> = (-5..=15)
+            .flat_map(|y| (-5..=15).map(move |x| (x, y)))
+            .filter(|&(x, y)| inside_circle(5, 5, 6, x, y))
+            .collect();
+        assert_eq!(cells, inside);
+    }
+
+    #[test]
+    fn line_footprint() {
+        let diagonal = vec![(0, 0), (1, 1), (2, 2), (3, 3)];
+        assert_eq!(line((0, 0), (3, 3), 1), diagonal);
+        assert_eq!(set(&line((3, 3), (0, 0), 1)), set(&diagonal));
+
+        //No gaps between the ends of a steep line
+        let cells = set(&line((0, 0), (2, 6), 1));
+        for y in 0..=6 {
+            assert!(cells.iter().any(|cell| cell.1 == y));
+        }
+        assert!(cells.contains(&(0, 0)) && cells.contains(&(2, 6)));
+    }
+
+    #[test]
+    fn rect_footprint() {
+        let filled = rect((3, 2), (0, 0), true, 1);
+        assert_eq!(filled.len(), 12);
+        assert_eq!(set(&filled).len(), 12);
+        assert!(filled
+            .iter()
+            .all(|&(x, y)| (0..=3).contains(&x) && (0..=2).contains(&y)));
+
+        //Only the border of the rectangle, without its inside
+        let outline = set(&rect((0, 0), (3, 2), false, 1));
+        let border: HashSet<(i32, i32)> = set(&filled)
+            .into_iter()
+            .filter(|&(x, y)| x == 0 || x == 3 || y == 0 || y == 2)
+            .collect();
+        assert_eq!(outline, border);
+    }
+
+    #[test]
+    fn square_footprint() {
+        //The longest side is used towards b
+        let cells = square((0, 0), (2, -5), true, 1);
+        assert_eq!(cells.len(), 36);
+        assert!(cells
+            .iter()
+            .all(|&(x, y)| (0..=5).contains(&x) && (-5..=0).contains(&y)));
+        assert_eq!(
+            set(&square((0, 0), (-4, 1), false, 1)),
+            set(&rect((0, 0), (-4, 4), false, 1))
+        );
+    }
+
+    #[test]
+    fn spray_density() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let full = circle(50, 50, 20);
+        assert!(spray(50, 50, 20, 0.0, &mut rng).is_empty());
+        assert_eq!(spray(50, 50, 20, 1.0, &mut rng), full);
+
+        let cells = spray(50, 50, 20, 0.25, &mut rng);
+        assert!(set(&cells).is_subset(&set(&full)));
+        let fraction = cells.len() as f64 / full.len() as f64;
+        assert!((0.2..0.3).contains(&fraction), "{fraction}");
+    }
+
+    #[test]
+    fn flood_fill_stops_at_other_materials() {
+        //A wall down the grid and a staircase of walls that only
+        //touch each other at their corners
+        let mut grid = SandGrid::with_seed(6, 6, 1);
+        for y in 0..6 {
+            grid.set_sand(3, y, MaterialId::WALL);
+        }
+        grid.set_sand(2, 3, MaterialId::WALL);
+        grid.set_sand(1, 4, MaterialId::WALL);
+        grid.set_sand(0, 5, MaterialId::WALL);
+
+        let cells = flood_fill(&grid, 0, 0, MaterialId::SAND);
+        assert_eq!(set(&cells).len(), cells.len());
+        assert_eq!(cells.len(), 12);
+        for &(x, y) in &cells {
+            assert_eq!(grid.get_sand(x as usize, y as usize), MaterialId::AIR);
+            assert!(x < 3);
+        }
+        //Air cut off by the staircase is only touched at the corners
+        for cell in [(2, 4), (2, 5), (1, 5)] {
+            assert!(!cells.contains(&cell));
+        }
+
+        //Filling walls only covers the walls that touch by their sides
+        assert_eq!(flood_fill(&grid, 3, 0, MaterialId::SAND).len(), 7);
+        assert!(flood_fill(&grid, 0, 0, MaterialId::AIR).is_empty());
+        assert!(flood_fill(&grid, 6, 0, MaterialId::SAND).is_empty());
+    }
+
+    //A row of air, a row of walls, a row of water and a row of sand
+    fn striped_grid(registry: &MaterialRegistry) -> SandGrid {
+        let mut grid = SandGrid::with_seed(4, 4, 1);
+        for (y, sand) in [MaterialId::WALL, MaterialId::WATER, MaterialId::SAND]
+            .into_iter()
+            .enumerate()
+        {
+            let row: Vec<(i32, i32)> = (0..4).map(|x| (x, y as i32 + 1)).collect();
+            grid.place_cells(sand, &row, &BrushFilter::default(), registry);
+        }
+        grid
+    }
+
+    //What each row holds after painting oil over the whole grid
+    fn paint(filter: &BrushFilter) -> Vec<MaterialId> {
+        let registry = MaterialRegistry::builtin();
+        let mut grid = striped_grid(&registry);
+        let cells = rect((0, 0), (3, 3), true, 1);
+        grid.place_cells(MaterialId::OIL, &cells, filter, &registry);
+
+        (0..4)
+            .map(|y| {
+                let sand = grid.get_sand(0, y);
+                assert!((0..4).all(|x| grid.get_sand(x, y) == sand));
+                sand
+            })
+            .collect()
+    }
+
+    #[test]
+    fn protected_walls_survive() {
+        let oil = MaterialId::OIL;
+        assert_eq!(
+            paint(&BrushFilter::protect(MaterialId::WALL)),
+            vec![oil, MaterialId::WALL, oil, oil]
+        );
+        assert_eq!(paint(&BrushFilter::default()), vec![oil; 4]);
+    }
+
+    #[test]
+    fn only_paints_over() {
+        let oil = MaterialId::OIL;
+        assert_eq!(
+            paint(&BrushFilter::only(MaterialId::WATER)),
+            vec![MaterialId::AIR, MaterialId::WALL, oil, MaterialId::SAND]
+        );
+        assert_eq!(
+            paint(&BrushFilter::air_only()),
+            vec![oil, MaterialId::WALL, MaterialId::WATER, MaterialId::SAND]
+        );
+
+        //Protecting wins over only painting over a material
+        let mut filter = BrushFilter::only(MaterialId::WALL);
+        filter.protect.insert(MaterialId::WALL);
+        assert_eq!(
+            paint(&filter),
+            vec![
+                MaterialId::AIR,
+                MaterialId::WALL,
+                MaterialId::WATER,
+                MaterialId::SAND
+            ]
+        );
+    }
+}
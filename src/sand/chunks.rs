@@ -0,0 +1,125 @@
+//The grid is divided into chunks that each track the rectangle of cells
+//that can update, so that only the parts of the grid where something is
+//happening have to be visited each step
+
+pub const CHUNK_SIZE: usize = 32;
+
+//Inclusive bounds of the cells that may need updating in a chunk,
+//empty when min is greater than max
+#[derive(Clone, Copy)]
+pub(crate) struct DirtyRect {
+    pub min_x: usize,
+    pub min_y: usize,
+    pub max_x: usize,
+    pub max_y: usize,
+}
+
+impl DirtyRect {
+    pub const EMPTY: Self = Self {
+        min_x: usize::MAX,
+        min_y: usize::MAX,
+        max_x: 0,
+        max_y: 0,
+    };
+
+    pub fn is_empty(&self) -> bool {
+        self.min_x > self.max_x || self.min_y > self.max_y
+    }
+
+    pub fn contains_row(&self, y: usize) -> bool {
+        y >= self.min_y && y <= self.max_y
+    }
+
+    pub fn include(&mut self, x: usize, y: usize) {
+        self.min_x = self.min_x.min(x);
+        self.min_y = self.min_y.min(y);
+        self.max_x = self.max_x.max(x);
+        self.max_y = self.max_y.max(y);
+    }
+}
+
+pub(crate) struct Chunks {
+    width: usize,
+    height: usize,
+    pub columns: usize,
+    pub rows: usize,
+    //Cells that can update or were updated this step
+    pub dirty: Vec<DirtyRect>,
+    //Chunks with cells that are not at the ambient temperature
+    pub hot: Vec<bool>,
+    //Chunks with sparks or batteries, or where they might have been placed
+    pub charged: Vec<bool>,
+}
+
+impl Chunks {
+    //Every chunk starts dirty, hot and charged, they settle down after a step
+    pub fn new(width: usize, height: usize) -> Self {
+        let columns = width.div_ceil(CHUNK_SIZE);
+        let rows = height.div_ceil(CHUNK_SIZE);
+
+        let mut chunks = Self {
+            width,
+            height,
+            columns,
+            rows,
+            dirty: Vec::new(),
+            hot: vec![true; columns * rows],
+            charged: vec![true; columns * rows],
+        };
+        chunks.dirty = (0..columns * rows).map(|i| chunks.bounds(i)).collect();
+
+        chunks
+    }
+
+    //All the cells in a chunk
+    pub fn bounds(&self, i: usize) -> DirtyRect {
+        let (min_x, min_y) = (
+            (i % self.columns) * CHUNK_SIZE,
+            (i / self.columns) * CHUNK_SIZE,
+        );
+        DirtyRect {
+            min_x,
+            min_y,
+            max_x: (min_x + CHUNK_SIZE).min(self.width) - 1,
+            max_y: (min_y + CHUNK_SIZE).min(self.height) - 1,
+        }
+    }
+
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        (y / CHUNK_SIZE) * self.columns + x / CHUNK_SIZE
+    }
+
+    pub fn mark_dirty(&mut self, x: usize, y: usize) {
+        let i = self.index(x, y);
+        self.dirty[i].include(x, y);
+    }
+
+    pub fn mark_hot(&mut self, x: usize, y: usize) {
+        let i = self.index(x, y);
+        self.hot[i] = true;
+    }
+
+    pub fn mark_charged(&mut self, x: usize, y: usize) {
+        let i = self.index(x, y);
+        self.charged[i] = true;
+    }
+
+    //Chunks where heat can flow, every cell in other chunks and
+    //their neighbors is at the ambient temperature
+    pub fn heat_active(&self) -> Vec<bool> {
+        let mut active = vec![false; self.hot.len()];
+
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let i = row * self.columns + column;
+                active[i] = self.hot[i]
+                    || (column > 0 && self.hot[i - 1])
+                    || (column + 1 < self.columns && self.hot[i + 1])
+                    || (row > 0 && self.hot[i - self.columns])
+                    || (row + 1 < self.rows && self.hot[i + self.columns]);
+            }
+        }
+
+        active
+    }
+}
@@ -0,0 +1,233 @@
+//Sparks travelling through conductors, a cellular automaton like
+//Wireworld layered on top of the grid
+//
+//An idle conductor sparks when one of its 8 neighbors is sparking or
+//is a battery. A spark lasts one step and leaves the conductor spent
+//for one step, so sparks only ever move away from where they came from.
+//Sparks heat up the materials touching them, which sets fire to
+//flammable materials and explosives.
+
+use super::{
+    material_registry::{MaterialId, MaterialRegistry},
+    sand_properties::Electricity,
+    SandGrid,
+};
+
+//Temperature of the materials touching a spark
+pub const SPARK_TEMPERATURE: f32 = 300.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Charge {
+    None,
+    Spark,
+    //Can not spark again until the next step
+    Spent,
+}
+
+impl SandGrid {
+    pub fn get_charge(&self, x: usize, y: usize) -> Charge {
+        if self.out_of_bounds(x as isize, y as isize) {
+            return Charge::None;
+        }
+
+        self.grid[y * self.width + x].charge()
+    }
+
+    //Sparks a conductor, other materials can not hold a charge
+    pub fn spark(&mut self, x: usize, y: usize, registry: &MaterialRegistry) {
+        if self.out_of_bounds(x as isize, y as isize) {
+            return;
+        }
+
+        let conductor = registry
+            .get_sand_property(self.get_sand(x, y))
+            .is_some_and(|properties| properties.electricity == Electricity::Conductor);
+        if conductor {
+            self.grid[y * self.width + x].set_charge(Charge::Spark);
+            self.chunks.mark_charged(x, y);
+        }
+    }
+
+    //A moving particle takes its charge with it and leaves air behind
+    pub(crate) fn move_charge(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
+        if self.out_of_bounds(x1 as isize, y1 as isize)
+            || self.out_of_bounds(x2 as isize, y2 as isize)
+        {
+            return;
+        }
+
+        let charge = self.grid[y1 * self.width + x1].charge();
+        self.grid[y1 * self.width + x1].set_charge(Charge::None);
+        self.grid[y2 * self.width + x2].set_charge(charge);
+        if charge != Charge::None {
+            self.chunks.mark_charged(x2, y2);
+        }
+    }
+
+    pub(crate) fn swap_charge(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
+        if self.out_of_bounds(x1 as isize, y1 as isize)
+            || self.out_of_bounds(x2 as isize, y2 as isize)
+        {
+            return;
+        }
+
+        let (i, j) = (y1 * self.width + x1, y2 * self.width + x2);
+        let (charge1, charge2) = (self.grid[i].charge(), self.grid[j].charge());
+        self.grid[i].set_charge(charge2);
+        self.grid[j].set_charge(charge1);
+        if charge2 != Charge::None {
+            self.chunks.mark_charged(x1, y1);
+        }
+        if charge1 != Charge::None {
+            self.chunks.mark_charged(x2, y2);
+        }
+    }
+
+    //Every cell changes at the same time like heat does, the new
+    //charges are worked out before any of them are set. Sparks and
+    //batteries spark their neighbors so idle conductors away from
+    //them cost nothing.
+    pub(crate) fn update_electricity(&mut self, registry: &MaterialRegistry) {
+        let mut electricity = [Electricity::None; 256];
+        for id in registry.ids() {
+            if let Some(properties) = registry.get_sand_property(id) {
+                electricity[id.0 as usize] = properties.electricity;
+            }
+        }
+
+        let chunk_count = self.chunks.charged.len();
+        let active = std::mem::replace(&mut self.chunks.charged, vec![false; chunk_count]);
+
+        let mut changes = Vec::new();
+        for chunk in (0..active.len()).filter(|i| active[*i]) {
+            let bounds = self.chunks.bounds(chunk);
+            for y in bounds.min_y..=bounds.max_y {
+                for x in bounds.min_x..=bounds.max_x {
+                    let i = y * self.width + x;
+                    let charge = self.grid[i].charge();
+
+                    match (electricity[self.grid[i].sand_type.0 as usize], charge) {
+                        (Electricity::Conductor, Charge::Spark) => {
+                            changes.push((i, Charge::Spent));
+                            self.spark_neighbors(x, y, &electricity, &mut changes);
+                        }
+                        (Electricity::Conductor, Charge::Spent) => {
+                            changes.push((i, Charge::None));
+                        }
+                        (Electricity::Conductor, Charge::None) => {}
+                        (Electricity::Battery, _) => {
+                            self.chunks.mark_charged(x, y);
+                            self.spark_neighbors(x, y, &electricity, &mut changes);
+                        }
+                        //A conductor that turned into something else loses its charge
+                        (Electricity::None, Charge::None) => {}
+                        (Electricity::None, _) => changes.push((i, Charge::None)),
+                    }
+                }
+            }
+        }
+
+        for (i, charge) in changes {
+            //A cell sparked by several neighbors is in the list more than once
+            if self.grid[i].charge() == charge {
+                continue;
+            }
+
+            self.grid[i].set_charge(charge);
+
+            let (x, y) = (i % self.width, i / self.width);
+            if charge != Charge::None {
+                self.chunks.mark_charged(x, y);
+            }
+            if charge == Charge::Spark {
+                self.heat_around_spark(x, y, &electricity);
+            }
+        }
+    }
+
+    //Idle conductors among the 8 neighbors spark
+    fn spark_neighbors(
+        &self,
+        x: usize,
+        y: usize,
+        electricity: &[Electricity; 256],
+        changes: &mut Vec<(usize, Charge)>,
+    ) {
+        for ny in y.saturating_sub(1)..=(y + 1).min(self.height - 1) {
+            for nx in x.saturating_sub(1)..=(x + 1).min(self.width - 1) {
+                let i = ny * self.width + nx;
+                if electricity[self.grid[i].sand_type.0 as usize] == Electricity::Conductor
+                    && self.grid[i].charge() == Charge::None
+                {
+                    changes.push((i, Charge::Spark));
+                }
+            }
+        }
+    }
+
+    //Heats the 4 neighbors that are not conductors or air,
+    //they catch fire or melt like they would next to a fire
+    fn heat_around_spark(&mut self, x: usize, y: usize, electricity: &[Electricity; 256]) {
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+            if self.out_of_bounds(nx, ny) {
+                continue;
+            }
+
+            let (nx, ny) = (nx as usize, ny as usize);
+            let sand = self.get_sand(nx, ny);
+            if sand == MaterialId::AIR
+                || electricity[sand.0 as usize] != Electricity::None
+                || self.get_temperature(nx, ny) >= SPARK_TEMPERATURE
+            {
+                continue;
+            }
+
+            self.set_temperature(nx, ny, SPARK_TEMPERATURE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sand::brush::BrushFilter;
+
+    fn count(grid: &SandGrid, sand: MaterialId) -> usize {
+        (0..grid.height)
+            .flat_map(|y| (0..grid.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| grid.get_sand(x, y) == sand)
+            .count()
+    }
+
+    //A battery at the left end of a wire and a single explosive
+    //at the right end, with a heap of sand next to the explosive
+    #[test]
+    fn spark_detonates_explosive() {
+        let registry = MaterialRegistry::builtin();
+        let id = |name: &str| registry.id(name).unwrap();
+        let filter = BrushFilter::default();
+
+        let floor: Vec<(i32, i32)> = (0..64).map(|x| (x, 41)).collect();
+        let wire: Vec<(i32, i32)> = (1..20).map(|x| (x, 40)).collect();
+        let heap: Vec<(i32, i32)> = (36..41)
+            .flat_map(|y| (26..34).map(move |x| (x, y)))
+            .collect();
+        for parallel in [false, true] {
+            let mut grid = SandGrid::with_seed(64, 64, 1);
+            grid.set_parallel(parallel);
+            grid.place_cells(MaterialId::WALL, &floor, &filter, &registry);
+            grid.place_cells(id("Wire"), &wire, &filter, &registry);
+            grid.place_cells(id("Battery"), &[(0, 40)], &filter, &registry);
+            grid.place_cells(MaterialId::EXPLOSIVE, &[(20, 40)], &filter, &registry);
+            grid.place_cells(MaterialId::SAND, &heap, &filter, &registry);
+
+            for frame in 0..40 {
+                grid.update_sand(&registry, frame);
+            }
+
+            assert_eq!(count(&grid, MaterialId::EXPLOSIVE), 0);
+            assert_eq!(count(&grid, MaterialId::SAND), 0);
+        }
+    }
+}
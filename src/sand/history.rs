@@ -0,0 +1,363 @@
+//Undo and redo for edits made to a grid
+//
+//Brush strokes are stored as the cells they changed, with what the
+//cells held before and after the stroke. Snapshots of the whole grid
+//are stored in the save file format, which keeps runs of the same
+//material small.
+
+use super::{
+    brush::{self, BrushFilter},
+    material_registry::{MaterialId, MaterialRegistry},
+    sand_file::SandFileError,
+    SandGrid,
+};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Clone, Copy, PartialEq)]
+struct Cell {
+    sand: MaterialId,
+    temperature: f32,
+    cloned: MaterialId,
+}
+
+struct CellChange {
+    index: usize,
+    before: Cell,
+    after: Cell,
+}
+
+enum Edit {
+    Stroke(Vec<CellChange>),
+    Snapshot(Vec<u8>),
+}
+
+impl Edit {
+    fn size(&self) -> usize {
+        match self {
+            Edit::Stroke(changes) => changes.len() * std::mem::size_of::<CellChange>(),
+            Edit::Snapshot(bytes) => bytes.len(),
+        }
+    }
+}
+
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    //Cells touched by the stroke being drawn and what they held before it
+    stroke: Option<HashMap<usize, Cell>>,
+    //The oldest edits are forgotten when the history uses more memory than this
+    max_bytes: usize,
+    //Memory used by the edits in undo and redo, kept up to date as they
+    //are added and removed so trimming does not have to add them up
+    bytes: usize,
+}
+
+impl SandGrid {
+    fn cell(&self, index: usize) -> Cell {
+        Cell {
+            sand: self.grid[index].sand_type,
+            temperature: self.temperature[index],
+            cloned: self.cloned[index],
+        }
+    }
+
+    //Puts back a cell the same way placing sand does, so it starts moving again
+    fn restore_cell(&mut self, index: usize, cell: Cell) {
+        let (x, y) = (index % self.width, index / self.width);
+        self.set_sand(x, y, cell.sand);
+        self.set_temperature(x, y, cell.temperature);
+        self.cloned[index] = cell.cloned;
+        self.grid[index].set_updated(false);
+        self.set_can_update(x, y);
+        self.set_adjacent_can_update(x, y);
+        self.chunks.mark_charged(x, y);
+    }
+}
+
+impl History {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            stroke: None,
+            max_bytes,
+            bytes: 0,
+        }
+    }
+
+    //Memory used by the edits that can be undone and redone
+    pub fn size(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.bytes > self.max_bytes {
+            let Some(edit) = self.undo.pop_front() else {
+                break;
+            };
+            self.bytes -= edit.size();
+        }
+    }
+
+    fn push(&mut self, edit: Edit) {
+        for edit in self.redo.drain(..) {
+            self.bytes -= edit.size();
+        }
+        self.bytes += edit.size();
+        self.undo.push_back(edit);
+        self.trim();
+    }
+
+    pub fn is_drawing(&self) -> bool {
+        self.stroke.is_some()
+    }
+
+    //Places sand like SandGrid::place_sand and remembers the cells it
+    //covered, a stroke goes on until end_stroke is called
+    pub fn place_sand(
+        &mut self,
+        grid: &mut SandGrid,
+        sand: MaterialId,
+        posx: i32,
+        posy: i32,
+        radius: u32,
+        registry: &MaterialRegistry,
+    ) {
+        self.place_cells(
+            grid,
+            sand,
+            &brush::circle(posx, posy, radius),
+            &BrushFilter::default(),
+            registry,
+        );
+    }
+
+    //Places sand like SandGrid::place_cells as part of the current stroke
+    pub fn place_cells(
+        &mut self,
+        grid: &mut SandGrid,
+        sand: MaterialId,
+        cells: &[(i32, i32)],
+        filter: &BrushFilter,
+        registry: &MaterialRegistry,
+    ) {
+        let stroke = self.stroke.get_or_insert_with(HashMap::new);
+
+        for (x, y) in cells.iter().copied() {
+            if grid.out_of_bounds(x as isize, y as isize) {
+                continue;
+            }
+
+            let index = y as usize * grid.width + x as usize;
+            stroke.entry(index).or_insert_with(|| grid.cell(index));
+        }
+
+        grid.place_cells(sand, cells, filter, registry);
+    }
+
+    //Finishes the current stroke, cells that ended up the same
+    //as they were before are not kept
+    pub fn end_stroke(&mut self, grid: &SandGrid) {
+        let Some(stroke) = self.stroke.take() else {
+            return;
+        };
+
+        let mut changes: Vec<CellChange> = stroke
+            .into_iter()
+            .map(|(index, before)| CellChange {
+                index,
+                before,
+                after: grid.cell(index),
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+        changes.sort_by_key(|change| change.index);
+
+        if !changes.is_empty() {
+            self.push(Edit::Stroke(changes));
+        }
+    }
+
+    //Remembers the whole grid, undoing up to this point puts every cell
+    //back the way it is now
+    pub fn snapshot(
+        &mut self,
+        grid: &SandGrid,
+        registry: &MaterialRegistry,
+    ) -> Result<(), SandFileError> {
+        self.end_stroke(grid);
+        let bytes = grid.snapshot(registry)?;
+        self.push(Edit::Snapshot(bytes));
+        Ok(())
+    }
+
+    //Returns false if there was nothing to undo
+    pub fn undo(
+        &mut self,
+        grid: &mut SandGrid,
+        registry: &MaterialRegistry,
+    ) -> Result<bool, SandFileError> {
+        self.end_stroke(grid);
+        let Some(edit) = self.undo.pop_back() else {
+            return Ok(false);
+        };
+        self.bytes -= edit.size();
+
+        let edit = Self::apply(edit, grid, registry, true)?;
+        self.bytes += edit.size();
+        self.redo.push(edit);
+        self.trim();
+        Ok(true)
+    }
+
+    //Returns false if there was nothing to redo
+    pub fn redo(
+        &mut self,
+        grid: &mut SandGrid,
+        registry: &MaterialRegistry,
+    ) -> Result<bool, SandFileError> {
+        self.end_stroke(grid);
+        let Some(edit) = self.redo.pop() else {
+            return Ok(false);
+        };
+        self.bytes -= edit.size();
+
+        let edit = Self::apply(edit, grid, registry, false)?;
+        self.bytes += edit.size();
+        self.undo.push_back(edit);
+        self.trim();
+        Ok(true)
+    }
+
+    //Undoes or redoes an edit and returns the edit that reverses it,
+    //a snapshot is swapped with a snapshot of the grid as it is now
+    fn apply(
+        edit: Edit,
+        grid: &mut SandGrid,
+        registry: &MaterialRegistry,
+        undo: bool,
+    ) -> Result<Edit, SandFileError> {
+        match edit {
+            Edit::Stroke(changes) => {
+                for change in &changes {
+                    let cell = if undo { change.before } else { change.after };
+                    grid.restore_cell(change.index, cell);
+                }
+                Ok(Edit::Stroke(changes))
+            }
+            Edit::Snapshot(bytes) => {
+                let current = grid.snapshot(registry)?;
+                grid.read_into(bytes.as_slice(), registry)?;
+                Ok(Edit::Snapshot(current))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(history: &mut History, grid: &mut SandGrid, sand: MaterialId, x: i32) {
+        let registry = MaterialRegistry::builtin();
+        let filter = BrushFilter::default();
+        history.place_cells(grid, sand, &[(x, 0), (x, 1)], &filter, &registry);
+        history.end_stroke(grid);
+    }
+
+    //What size adds up from the edits themselves
+    fn counted_size(history: &History) -> usize {
+        history
+            .undo
+            .iter()
+            .chain(history.redo.iter())
+            .map(Edit::size)
+            .sum()
+    }
+
+    #[test]
+    fn undo_redo_stroke() {
+        let registry = MaterialRegistry::builtin();
+        let mut grid = SandGrid::with_seed(8, 8, 1);
+        let mut history = History::new(usize::MAX);
+        stroke(&mut history, &mut grid, MaterialId::WALL, 3);
+        assert_eq!(grid.get_sand(3, 1), MaterialId::WALL);
+
+        assert!(history.undo(&mut grid, &registry).unwrap());
+        assert_eq!(grid.get_sand(3, 0), MaterialId::AIR);
+        assert_eq!(grid.get_sand(3, 1), MaterialId::AIR);
+        assert!(!history.undo(&mut grid, &registry).unwrap());
+
+        assert!(history.redo(&mut grid, &registry).unwrap());
+        assert_eq!(grid.get_sand(3, 0), MaterialId::WALL);
+        assert_eq!(grid.get_sand(3, 1), MaterialId::WALL);
+        assert!(!history.redo(&mut grid, &registry).unwrap());
+        assert_eq!(history.size(), counted_size(&history));
+    }
+
+    //Undoing past a snapshot puts back the whole grid, including cells
+    //the simulation changed after it was taken
+    #[test]
+    fn undo_snapshot() {
+        let registry = MaterialRegistry::builtin();
+        let mut grid = SandGrid::with_seed(8, 8, 1);
+        let mut history = History::new(usize::MAX);
+        stroke(&mut history, &mut grid, MaterialId::WALL, 1);
+        history.snapshot(&grid, &registry).unwrap();
+        grid.set_sand(5, 5, MaterialId::STONE);
+        stroke(&mut history, &mut grid, MaterialId::WALL, 6);
+
+        assert!(history.undo(&mut grid, &registry).unwrap());
+        assert_eq!(grid.get_sand(6, 0), MaterialId::AIR);
+        assert_eq!(grid.get_sand(5, 5), MaterialId::STONE);
+
+        assert!(history.undo(&mut grid, &registry).unwrap());
+        assert_eq!(grid.get_sand(5, 5), MaterialId::AIR);
+        assert_eq!(grid.get_sand(1, 0), MaterialId::WALL);
+
+        //Redoing the snapshot brings back the grid as it was when undone
+        assert!(history.redo(&mut grid, &registry).unwrap());
+        assert_eq!(grid.get_sand(5, 5), MaterialId::STONE);
+        assert_eq!(history.size(), counted_size(&history));
+    }
+
+    #[test]
+    fn trim_oldest() {
+        let registry = MaterialRegistry::builtin();
+        let mut grid = SandGrid::with_seed(8, 8, 1);
+        let mut history = History::new(usize::MAX);
+        for x in 0..4 {
+            stroke(&mut history, &mut grid, MaterialId::WALL, x);
+        }
+        let stroke_size = history.size() / 4;
+
+        history.set_max_bytes(stroke_size * 2);
+        assert_eq!(history.size(), stroke_size * 2);
+        assert_eq!(history.size(), counted_size(&history));
+        for x in [3, 2] {
+            assert!(history.undo(&mut grid, &registry).unwrap());
+            assert_eq!(grid.get_sand(x, 0), MaterialId::AIR);
+        }
+        assert!(!history.undo(&mut grid, &registry).unwrap());
+        assert_eq!(grid.get_sand(1, 0), MaterialId::WALL);
+
+        //A new stroke clears the redo edits before anything else is trimmed
+        stroke(&mut history, &mut grid, MaterialId::WALL, 7);
+        assert!(!history.can_redo());
+        assert_eq!(history.size(), stroke_size);
+        assert_eq!(history.size(), counted_size(&history));
+    }
+}
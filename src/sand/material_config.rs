@@ -0,0 +1,622 @@
+//Loading material properties from a TOML file,
+//see materials.toml for the built-in materials and the format
+
+use super::{
+    material_registry::{MaterialId, MaterialRegistry},
+    sand_properties::{
+        Behavior, Blast, Burn, BurnOut, Decay, Electricity, Emit, Germination, GrowDirection,
+        Growth, Movement, NeighborTransform, PhaseChange, SandProperties,
+    },
+    temperature::AMBIENT_TEMPERATURE,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialFile {
+    #[serde(default)]
+    material: Vec<MaterialEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialEntry {
+    name: String,
+    color: [u8; 3],
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default)]
+    movement: Movement,
+    #[serde(default = "default_repose")]
+    repose: usize,
+    #[serde(default)]
+    behavior: Behavior,
+    blast: Option<BlastEntry>,
+    #[serde(default = "default_hardness")]
+    hardness: f32,
+    #[serde(default)]
+    electricity: Electricity,
+    #[serde(default)]
+    can_replace: Vec<String>,
+    #[serde(default)]
+    replace_with: BTreeMap<String, String>,
+    #[serde(default = "default_density")]
+    density: f32,
+    #[serde(default)]
+    transform: Vec<TransformEntry>,
+    #[serde(default = "ambient_temperature")]
+    temperature: f32,
+    #[serde(default)]
+    heat_source: bool,
+    #[serde(default)]
+    conductivity: f32,
+    #[serde(default)]
+    cooling: f32,
+    below: Option<PhaseChangeEntry>,
+    above: Option<PhaseChangeEntry>,
+    decay: Option<DecayEntry>,
+    burn_out: Option<BurnOutEntry>,
+    grow: Option<GrowEntry>,
+    germinate: Option<GerminateEntry>,
+    emit: Option<EmitEntry>,
+    burn: Option<BurnEntry>,
+}
+
+fn ambient_temperature() -> f32 {
+    AMBIENT_TEMPERATURE
+}
+
+fn default_density() -> f32 {
+    SandProperties::empty().density
+}
+
+fn default_repose() -> usize {
+    SandProperties::empty().repose
+}
+
+fn default_hardness() -> f32 {
+    SandProperties::empty().hardness
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PhaseChangeEntry {
+    temperature: f32,
+    into: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DecayEntry {
+    lifetime: u32,
+    into: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BurnOutEntry {
+    into: String,
+    probability: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlastEntry {
+    radius: usize,
+    energy: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BurnEntry {
+    spreads_to: String,
+    interval: u32,
+    into: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EmitEntry {
+    material: String,
+    interval: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GrowEntry {
+    probability: f64,
+    #[serde(default)]
+    direction: GrowDirection,
+    needs: Option<String>,
+    #[serde(default)]
+    consumes: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GerminateEntry {
+    on: String,
+    near: String,
+    into: String,
+    probability: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformEntry {
+    neighbor: String,
+    into: String,
+    min_count: u32,
+    max_count: u32,
+    probability: f64,
+}
+
+#[derive(Debug)]
+pub enum MaterialConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    //There are more materials than can fit in a MaterialId
+    TooManyMaterials,
+    //A material refers to another material that does not exist
+    UnknownReference { material: String, reference: String },
+    DuplicateMaterial(String),
+    InvalidTransform { material: String, reason: String },
+    InvalidProperty { material: String, reason: String },
+}
+
+impl fmt::Display for MaterialConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::TooManyMaterials => write!(f, "too many materials"),
+            Self::UnknownReference {
+                material,
+                reference,
+            } => write!(
+                f,
+                "material \"{material}\" refers to unknown material \"{reference}\""
+            ),
+            Self::DuplicateMaterial(name) => write!(f, "material \"{name}\" is declared twice"),
+            Self::InvalidTransform { material, reason } => {
+                write!(f, "invalid transform in material \"{material}\": {reason}")
+            }
+            Self::InvalidProperty { material, reason } => {
+                write!(f, "invalid property in material \"{material}\": {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MaterialConfigError {}
+
+impl From<std::io::Error> for MaterialConfigError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for MaterialConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Parse(err)
+    }
+}
+
+//Materials that were not declared in the config file can only be
+//referenced if they are built in, they are then left without properties
+fn lookup(
+    registry: &MaterialRegistry,
+    material: &str,
+    reference: &str,
+) -> Result<MaterialId, MaterialConfigError> {
+    registry
+        .id(reference)
+        .ok_or_else(|| MaterialConfigError::UnknownReference {
+            material: material.to_string(),
+            reference: reference.to_string(),
+        })
+}
+
+fn lookup_all(
+    registry: &MaterialRegistry,
+    material: &str,
+    references: &[String],
+) -> Result<Vec<MaterialId>, MaterialConfigError> {
+    references
+        .iter()
+        .map(|reference| lookup(registry, material, reference))
+        .collect()
+}
+
+impl MaterialEntry {
+    fn to_properties(
+        &self,
+        registry: &MaterialRegistry,
+    ) -> Result<SandProperties, MaterialConfigError> {
+        let can_replace = lookup_all(registry, &self.name, &self.can_replace)?;
+        let mut replace_with = Vec::new();
+        for (sand, turn_into) in &self.replace_with {
+            replace_with.push((
+                lookup(registry, &self.name, sand)?,
+                lookup(registry, &self.name, turn_into)?,
+            ));
+        }
+
+        let mut properties = SandProperties::from_vecs(Some(can_replace), Some(replace_with));
+        properties.density = self.density;
+        properties.movement = self.movement;
+        properties.behavior = self.behavior;
+        properties.electricity = self.electricity;
+        properties.temperature = self.temperature;
+        properties.heat_source = self.heat_source;
+        properties.conductivity = self.conductivity;
+        properties.cooling = self.cooling;
+
+        //Particles never look further than two cells below themselves
+        //so that chunks can be updated in parallel
+        if !(1..=2).contains(&self.repose) {
+            return Err(MaterialConfigError::InvalidProperty {
+                material: self.name.clone(),
+                reason: "repose must be 1 or 2".to_string(),
+            });
+        }
+        properties.repose = self.repose;
+
+        if !self.density.is_finite() || self.density <= 0.0 {
+            return Err(MaterialConfigError::InvalidProperty {
+                material: self.name.clone(),
+                reason: "density must be finite and greater than 0".to_string(),
+            });
+        }
+
+        let temperatures = [
+            Some(self.temperature),
+            self.below.as_ref().map(|below| below.temperature),
+            self.above.as_ref().map(|above| above.temperature),
+        ];
+        if temperatures.into_iter().flatten().any(|t| !t.is_finite()) {
+            return Err(MaterialConfigError::InvalidProperty {
+                material: self.name.clone(),
+                reason: "temperatures must be finite".to_string(),
+            });
+        }
+
+        if !(0.0..=1.0).contains(&self.conductivity) {
+            return Err(MaterialConfigError::InvalidProperty {
+                material: self.name.clone(),
+                reason: "conductivity must be between 0 and 1".to_string(),
+            });
+        }
+
+        if !(0.0..=1.0).contains(&self.cooling) {
+            return Err(MaterialConfigError::InvalidProperty {
+                material: self.name.clone(),
+                reason: "cooling must be between 0 and 1".to_string(),
+            });
+        }
+
+        if let (Some(below), Some(above)) = (&self.below, &self.above) {
+            if below.temperature >= above.temperature {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "below temperature must be lower than above temperature".to_string(),
+                });
+            }
+        }
+
+        if let Some(below) = &self.below {
+            properties.below = Some(PhaseChange {
+                temperature: below.temperature,
+                turn_into: lookup(registry, &self.name, &below.into)?,
+            });
+        }
+
+        if let Some(above) = &self.above {
+            properties.above = Some(PhaseChange {
+                temperature: above.temperature,
+                turn_into: lookup(registry, &self.name, &above.into)?,
+            });
+        }
+
+        if let Some(decay) = &self.decay {
+            if decay.lifetime == 0 {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "decay lifetime must be greater than 0".to_string(),
+                });
+            }
+
+            properties.decay = Some(Decay {
+                lifetime: decay.lifetime,
+                turn_into: lookup(registry, &self.name, &decay.into)?,
+            });
+        }
+
+        if let Some(burn_out) = &self.burn_out {
+            if !(0.0..=1.0).contains(&burn_out.probability) {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "burn_out probability must be between 0 and 1".to_string(),
+                });
+            }
+
+            properties.burn_out = Some(BurnOut {
+                turn_into: lookup(registry, &self.name, &burn_out.into)?,
+                probability: burn_out.probability,
+            });
+        }
+
+        if let Some(blast) = &self.blast {
+            if blast.radius == 0 || !blast.energy.is_finite() || blast.energy <= 0.0 {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "blast radius and energy must be finite and greater than 0".to_string(),
+                });
+            }
+
+            properties.blast = Blast {
+                radius: blast.radius,
+                energy: blast.energy,
+            };
+        }
+
+        if !self.hardness.is_finite() || self.hardness < 0.0 {
+            return Err(MaterialConfigError::InvalidProperty {
+                material: self.name.clone(),
+                reason: "hardness must be finite and can not be negative".to_string(),
+            });
+        }
+        properties.hardness = self.hardness;
+
+        if let Some(burn) = &self.burn {
+            if burn.interval == 0 {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "burn interval must be greater than 0".to_string(),
+                });
+            }
+
+            properties.burn = Some(Burn {
+                spreads_to: lookup(registry, &self.name, &burn.spreads_to)?,
+                interval: burn.interval,
+                turn_into: lookup(registry, &self.name, &burn.into)?,
+            });
+        }
+
+        if let Some(emit) = &self.emit {
+            if emit.interval == 0 {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "emit interval must be greater than 0".to_string(),
+                });
+            }
+
+            properties.emit = Some(Emit {
+                sand: lookup(registry, &self.name, &emit.material)?,
+                interval: emit.interval,
+            });
+        }
+
+        if let Some(grow) = &self.grow {
+            if !(0.0..=1.0).contains(&grow.probability) {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "grow probability must be between 0 and 1".to_string(),
+                });
+            }
+
+            if grow.consumes && grow.needs.is_none() {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "grow can only consume a material it needs".to_string(),
+                });
+            }
+
+            properties.growth = Some(Growth {
+                probability: grow.probability,
+                direction: grow.direction,
+                needs: grow
+                    .needs
+                    .as_ref()
+                    .map(|needs| lookup(registry, &self.name, needs))
+                    .transpose()?,
+                consumes: grow.consumes,
+            });
+        }
+
+        if let Some(germinate) = &self.germinate {
+            if !(0.0..=1.0).contains(&germinate.probability) {
+                return Err(MaterialConfigError::InvalidProperty {
+                    material: self.name.clone(),
+                    reason: "germinate probability must be between 0 and 1".to_string(),
+                });
+            }
+
+            properties.germination = Some(Germination {
+                on: lookup(registry, &self.name, &germinate.on)?,
+                near: lookup(registry, &self.name, &germinate.near)?,
+                turn_into: lookup(registry, &self.name, &germinate.into)?,
+                probability: germinate.probability,
+            });
+        }
+
+        for transform in &self.transform {
+            if transform.min_count > transform.max_count {
+                return Err(MaterialConfigError::InvalidTransform {
+                    material: self.name.clone(),
+                    reason: "min_count is greater than max_count".to_string(),
+                });
+            }
+
+            if !(0.0..=1.0).contains(&transform.probability) {
+                return Err(MaterialConfigError::InvalidTransform {
+                    material: self.name.clone(),
+                    reason: "probability must be between 0 and 1".to_string(),
+                });
+            }
+
+            properties.transforms.push(NeighborTransform {
+                neighbor: lookup(registry, &self.name, &transform.neighbor)?,
+                turn_into: lookup(registry, &self.name, &transform.into)?,
+                min_count: transform.min_count,
+                max_count: transform.max_count,
+                probability: transform.probability,
+            });
+        }
+
+        Ok(properties)
+    }
+}
+
+impl MaterialRegistry {
+    //Built-in materials keep their ids, new materials are given
+    //ids in the order they are declared
+    pub fn from_config_str(config: &str) -> Result<Self, MaterialConfigError> {
+        let material_file: MaterialFile = toml::from_str(config)?;
+        let mut registry = Self::new();
+
+        //Register every name first so materials can refer to
+        //materials that are declared after them
+        let mut declared = Vec::new();
+        for entry in &material_file.material {
+            let id = registry
+                .register(&entry.name)
+                .ok_or(MaterialConfigError::TooManyMaterials)?;
+
+            if declared.contains(&id) {
+                return Err(MaterialConfigError::DuplicateMaterial(entry.name.clone()));
+            }
+            declared.push(id);
+        }
+
+        for (entry, id) in material_file.material.iter().zip(declared) {
+            let properties = entry.to_properties(&registry)?;
+            if let Some(material) = registry.get_mut(id) {
+                material.color = (entry.color[0], entry.color[1], entry.color[2]);
+                material.hidden = entry.hidden;
+                material.properties = Some(properties);
+            }
+        }
+
+        registry.update_displacement();
+        Ok(registry)
+    }
+
+    pub fn from_config_file<P: AsRef<Path>>(path: P) -> Result<Self, MaterialConfigError> {
+        Self::from_config_str(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unknown_reference(config: &str) -> Option<(String, String)> {
+        match MaterialRegistry::from_config_str(config) {
+            Err(MaterialConfigError::UnknownReference {
+                material,
+                reference,
+            }) => Some((material, reference)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn unknown_can_replace() {
+        let config = r#"
+            [[material]]
+            name = "Sand"
+            color = [90, 60, 30]
+            can_replace = ["Slime"]
+        "#;
+        assert_eq!(
+            unknown_reference(config),
+            Some(("Sand".to_string(), "Slime".to_string()))
+        );
+    }
+
+    #[test]
+    fn unknown_replace_with() {
+        let config = r#"
+            [[material]]
+            name = "Sand"
+            color = [90, 60, 30]
+            replace_with = { Water = "Slime" }
+        "#;
+        assert_eq!(
+            unknown_reference(config),
+            Some(("Sand".to_string(), "Slime".to_string()))
+        );
+    }
+
+    #[test]
+    fn unknown_transform_target() {
+        let config = r#"
+            [[material]]
+            name = "Sand"
+            color = [90, 60, 30]
+
+            [[material.transform]]
+            neighbor = "Water"
+            into = "Slime"
+            min_count = 1
+            max_count = 4
+            probability = 0.5
+        "#;
+        assert_eq!(
+            unknown_reference(config),
+            Some(("Sand".to_string(), "Slime".to_string()))
+        );
+    }
+
+    #[test]
+    fn non_finite_properties() {
+        for property in [
+            "hardness = nan",
+            "hardness = inf",
+            "blast = { radius = 8, energy = nan }",
+            "blast = { radius = 8, energy = inf }",
+            "density = nan",
+            "density = inf",
+            "temperature = nan",
+            "temperature = -inf",
+            r#"below = { temperature = nan, into = "Air" }"#,
+            r#"below = { temperature = -inf, into = "Air" }"#,
+            r#"above = { temperature = nan, into = "Air" }"#,
+            r#"above = { temperature = inf, into = "Air" }"#,
+        ] {
+            let config = format!(
+                r#"
+                [[material]]
+                name = "Mud"
+                color = [90, 60, 30]
+                {property}
+                "#
+            );
+            assert!(matches!(
+                MaterialRegistry::from_config_str(&config),
+                Err(MaterialConfigError::InvalidProperty { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_color() {
+        for color in ["[300, 0, 0]", "[90, 60]", "\"brown\""] {
+            let config = format!(
+                r#"
+                [[material]]
+                name = "Sand"
+                color = {color}
+                "#
+            );
+            assert!(matches!(
+                MaterialRegistry::from_config_str(&config),
+                Err(MaterialConfigError::Parse(_))
+            ));
+        }
+    }
+}
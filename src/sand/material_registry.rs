@@ -0,0 +1,211 @@
+//Materials are identified by compact ids that are assigned when the
+//registry is built, the built-in materials always have the same ids
+//so the simulation can refer to them directly
+
+use super::{sand_properties::SandProperties, temperature::AMBIENT_TEMPERATURE};
+#[cfg(feature = "render")]
+use sdl2::pixels::Color;
+use std::collections::HashMap;
+
+const DEFAULT_MATERIALS: &str = include_str!("materials.toml");
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct MaterialId(pub u8);
+
+impl MaterialId {
+    pub const AIR: Self = Self(0);
+    pub const SAND: Self = Self(1);
+    pub const WATER: Self = Self(2);
+    pub const WALL: Self = Self(3);
+    pub const WOOD: Self = Self(4);
+    pub const FIRE: Self = Self(5);
+    pub const OIL: Self = Self(6);
+    pub const ACID: Self = Self(7);
+    pub const LAVA: Self = Self(8);
+    pub const STONE: Self = Self(9);
+    pub const EXPLOSIVE: Self = Self(10);
+    pub const EXPLOSION: Self = Self(11);
+    //Returned when reading outside of the grid, never registered
+    pub const OUT_OF_BOUNDS: Self = Self(u8::MAX);
+}
+
+//Names of the built-in materials, indexed by id
+pub const BUILTIN_MATERIALS: [&str; 12] = [
+    "Air",
+    "Sand",
+    "Water",
+    "Wall",
+    "Wood",
+    "Fire",
+    "Oil",
+    "Acid",
+    "Lava",
+    "Stone",
+    "Explosive",
+    "Explosion",
+];
+
+pub struct Material {
+    pub name: String,
+    pub color: (u8, u8, u8),
+    //Hidden materials are not shown in the menu and can not be
+    //picked when importing images
+    pub hidden: bool,
+    pub properties: Option<SandProperties>,
+}
+
+pub struct MaterialRegistry {
+    materials: Vec<Material>,
+    ids: HashMap<String, MaterialId>,
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl MaterialRegistry {
+    //Creates a registry with only the names of the built-in materials,
+    //they have no properties until they are defined
+    pub fn new() -> Self {
+        let mut registry = Self {
+            materials: Vec::new(),
+            ids: HashMap::new(),
+        };
+
+        for name in BUILTIN_MATERIALS {
+            registry.register(name);
+        }
+
+        registry
+    }
+
+    //Built-in materials, see materials.toml
+    pub fn builtin() -> Self {
+        Self::from_config_str(DEFAULT_MATERIALS).expect("built-in materials.toml is invalid")
+    }
+
+    //Returns the id of the material with this name, adding
+    //a new material if there is none, returns None if the
+    //registry is full
+    pub fn register(&mut self, name: &str) -> Option<MaterialId> {
+        if let Some(id) = self.id(name) {
+            return Some(id);
+        }
+
+        if self.materials.len() >= MaterialId::OUT_OF_BOUNDS.0 as usize {
+            return None;
+        }
+
+        let id = MaterialId(self.materials.len() as u8);
+        self.materials.push(Material {
+            name: name.to_string(),
+            color: (255, 255, 255),
+            hidden: false,
+            properties: None,
+        });
+        self.ids.insert(name.to_string(), id);
+        Some(id)
+    }
+
+    pub fn id(&self, name: &str) -> Option<MaterialId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn get(&self, id: MaterialId) -> Option<&Material> {
+        self.materials.get(id.0 as usize)
+    }
+
+    pub fn get_mut(&mut self, id: MaterialId) -> Option<&mut Material> {
+        self.materials.get_mut(id.0 as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = MaterialId> {
+        (0..self.materials.len()).map(|i| MaterialId(i as u8))
+    }
+
+    //Materials that were defined and can be placed,
+    //in the order they were registered
+    pub fn placeable(&self) -> impl Iterator<Item = MaterialId> + '_ {
+        self.ids().filter(|id| {
+            let material = &self.materials[id.0 as usize];
+            !material.hidden && material.properties.is_some()
+        })
+    }
+
+    pub fn name(&self, id: MaterialId) -> &str {
+        match self.get(id) {
+            Some(material) => &material.name,
+            _ => "",
+        }
+    }
+
+    pub fn get_sand_property(&self, id: MaterialId) -> Option<&SandProperties> {
+        self.get(id)?.properties.as_ref()
+    }
+
+    pub fn initial_temperature(&self, id: MaterialId) -> f32 {
+        match self.get_sand_property(id) {
+            Some(properties) => properties.temperature,
+            _ => AMBIENT_TEMPERATURE,
+        }
+    }
+
+    //Works out which fluids every material can swap places with, this
+    //has to be called again after changing the density or movement of
+    //a material. The chance of swapping is the difference in density
+    //relative to the lighter material, so materials of very different
+    //densities separate quickly and similar ones slowly.
+    pub fn update_displacement(&mut self) {
+        let fluids: Vec<(MaterialId, f32)> = self
+            .ids()
+            .filter(|id| *id != MaterialId::AIR)
+            .filter_map(|id| {
+                let properties = self.get_sand_property(id)?;
+                properties.is_fluid().then_some((id, properties.density))
+            })
+            .collect();
+
+        for material in &mut self.materials {
+            let properties = match &mut material.properties {
+                Some(properties) => properties,
+                _ => continue,
+            };
+
+            properties.sink_into.clear();
+            properties.rise_into.clear();
+            for (fluid, density) in &fluids {
+                let heavier = properties.density.max(*density);
+                let lighter = properties.density.min(*density);
+                let chance = ((heavier - lighter) / lighter).min(1.0) as f64;
+                if properties.density > *density {
+                    properties.sink_into.insert(*fluid, chance);
+                } else if properties.density < *density {
+                    properties.rise_into.insert(*fluid, chance);
+                }
+            }
+        }
+    }
+
+    pub fn sand_rgb(&self, id: MaterialId) -> (u8, u8, u8) {
+        match self.get(id) {
+            Some(material) => material.color,
+            _ => (255, 255, 255),
+        }
+    }
+
+    #[cfg(feature = "render")]
+    pub fn sand_color(&self, id: MaterialId) -> Color {
+        let (r, g, b) = self.sand_rgb(id);
+        Color::RGB(r, g, b)
+    }
+}
@@ -0,0 +1,258 @@
+//Updating chunks on several threads at once
+//
+//Chunks are split into 4 phases like the squares of a checkerboard so
+//that two chunks in the same phase are always a whole chunk apart. Each
+//chunk in a phase is copied into its own small grid together with a
+//border of HALO cells, updated on a thread and copied back. Particles
+//never read or write more than HALO cells away from themselves so the
+//copies never overlap, except for explosions which are done afterwards.
+
+use super::{
+    chunks::DirtyRect,
+    electricity::Charge,
+    material_registry::{MaterialId, MaterialRegistry},
+    sand_properties::Blast,
+    temperature::AMBIENT_TEMPERATURE,
+    update_sand, SandGrid,
+};
+
+const HALO: usize = 2;
+const PHASES: [(usize, usize); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+//A chunk copied out of the grid
+struct ChunkCopy {
+    //Cells of the grid that were copied, the chunk and its border
+    region: DirtyRect,
+    grid: SandGrid,
+}
+
+impl SandGrid {
+    //Parallel updates give the same kind of results as serial ones but
+    //not exactly the same, chunks are updated in a different order
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    pub fn is_parallel(&self) -> bool {
+        self.parallel
+    }
+
+    fn copy_chunk(&self, chunk: usize, seed: u64) -> ChunkCopy {
+        let bounds = self.chunks.bounds(chunk);
+        let region = DirtyRect {
+            min_x: bounds.min_x.saturating_sub(HALO),
+            min_y: bounds.min_y.saturating_sub(HALO),
+            max_x: (bounds.max_x + HALO).min(self.width - 1),
+            max_y: (bounds.max_y + HALO).min(self.height - 1),
+        };
+        let width = region.max_x - region.min_x + 1;
+        let height = region.max_y - region.min_y + 1;
+
+        let mut cells = Vec::with_capacity(width * height);
+        let mut temperature = Vec::with_capacity(width * height);
+        let mut cloned = Vec::with_capacity(width * height);
+        for y in region.min_y..=region.max_y {
+            let row = (y * self.width + region.min_x)..=(y * self.width + region.max_x);
+            cells.extend_from_slice(&self.grid[row.clone()]);
+            temperature.extend_from_slice(&self.temperature[row.clone()]);
+            cloned.extend_from_slice(&self.cloned[row]);
+        }
+
+        //Every chunk gets its own stream of random numbers so the result
+        //does not depend on which thread updated it
+        let mut grid = SandGrid::from_particles(width, height, cells, seed);
+        grid.rng.set_stream(chunk as u64);
+        grid.temperature = temperature;
+        grid.cloned = cloned;
+        grid.deferred_explosions = Some(Vec::new());
+        grid.frame = self.frame;
+
+        ChunkCopy { region, grid }
+    }
+
+    fn update_chunk_copy(
+        copy: &mut ChunkCopy,
+        rect: DirtyRect,
+        registry: &MaterialRegistry,
+        reverse: bool,
+    ) {
+        let region = copy.region;
+        for y in rect.min_y..=rect.max_y {
+            for xval in rect.min_x..=rect.max_x {
+                let x = if reverse {
+                    rect.max_x + rect.min_x - xval
+                } else {
+                    xval
+                };
+                copy.grid
+                    .update_pixel(x - region.min_x, y - region.min_y, registry);
+            }
+        }
+    }
+
+    fn merge_chunk_copy(&mut self, copy: ChunkCopy, explosions: &mut Vec<(usize, usize, Blast)>) {
+        let region = copy.region;
+        let width = region.max_x - region.min_x + 1;
+
+        for y in region.min_y..=region.max_y {
+            for x in region.min_x..=region.max_x {
+                let i = y * self.width + x;
+                let j = (y - region.min_y) * width + (x - region.min_x);
+
+                self.grid[i] = copy.grid.grid[j];
+                self.temperature[i] = copy.grid.temperature[j];
+                self.cloned[i] = copy.grid.cloned[j];
+
+                if self.grid[i].updated() || self.grid[i].can_update() {
+                    self.chunks.mark_dirty(x, y);
+                }
+                if self.temperature[i] != AMBIENT_TEMPERATURE {
+                    self.chunks.mark_hot(x, y);
+                }
+                //Sparks carried into another chunk are updated this step
+                if self.grid[i].charge() != Charge::None {
+                    self.chunks.mark_charged(x, y);
+                }
+            }
+        }
+
+        if let Some(deferred) = copy.grid.deferred_explosions {
+            explosions.extend(
+                deferred
+                    .into_iter()
+                    .map(|(x, y, blast)| (x + region.min_x, y + region.min_y, blast)),
+            );
+        }
+    }
+
+    pub(crate) fn update_pixels_parallel(&mut self, registry: &MaterialRegistry, frame: u32) {
+        let reverse = frame.is_multiple_of(2);
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let seed = self.random::<u64>();
+
+        for (phase_x, phase_y) in PHASES {
+            let jobs: Vec<(usize, DirtyRect)> = (0..self.chunks.dirty.len())
+                .filter(|i| {
+                    i % self.chunks.columns % 2 == phase_x && i / self.chunks.columns % 2 == phase_y
+                })
+                .map(|i| (i, self.chunks.dirty[i]))
+                .filter(|(_, rect)| !rect.is_empty())
+                .collect();
+
+            if jobs.is_empty() {
+                continue;
+            }
+
+            let grid = &*self;
+            let update_jobs = move |jobs: &[(usize, DirtyRect)]| -> Vec<ChunkCopy> {
+                jobs.iter()
+                    .map(|(chunk, rect)| {
+                        let mut copy = grid.copy_chunk(*chunk, seed);
+                        Self::update_chunk_copy(&mut copy, *rect, registry, reverse);
+                        copy
+                    })
+                    .collect()
+            };
+
+            let copies = if threads == 1 || jobs.len() == 1 {
+                update_jobs(&jobs)
+            } else {
+                std::thread::scope(|scope| {
+                    let handles: Vec<_> = jobs
+                        .chunks(jobs.len().div_ceil(threads))
+                        .map(|jobs| scope.spawn(move || update_jobs(jobs)))
+                        .collect();
+
+                    handles
+                        .into_iter()
+                        .flat_map(|handle| handle.join().expect("chunk update panicked"))
+                        .collect()
+                })
+            };
+
+            let mut explosions = Vec::new();
+            for copy in copies {
+                self.merge_chunk_copy(copy, &mut explosions);
+            }
+
+            if let Some(explosion_property) = registry.get_sand_property(MaterialId::EXPLOSION) {
+                for (x, y, blast) in explosions {
+                    update_sand::explode(x, y, self, explosion_property, &blast, registry);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sand::{brush::BrushFilter, chunks::CHUNK_SIZE};
+
+    fn count(grid: &SandGrid, sand: MaterialId) -> usize {
+        grid.grid
+            .iter()
+            .filter(|particle| particle.sand_type == sand)
+            .count()
+    }
+
+    //Nothing is lost or made where the copies of neighbouring chunks meet
+    #[test]
+    fn conserves_mass() {
+        let registry = MaterialRegistry::builtin();
+        for parallel in [false, true] {
+            let mut grid = SandGrid::with_seed(96, 96, 1);
+            grid.set_parallel(parallel);
+            grid.place_sand(MaterialId::SAND, 30, 20, 12, &registry);
+            grid.place_sand(MaterialId::WATER, 64, 30, 12, &registry);
+            let sand = count(&grid, MaterialId::SAND);
+            let water = count(&grid, MaterialId::WATER);
+
+            for frame in 0..200 {
+                grid.update_sand(&registry, frame);
+                assert_eq!(count(&grid, MaterialId::SAND), sand);
+                assert_eq!(count(&grid, MaterialId::WATER), water);
+            }
+        }
+    }
+
+    //The charge of a sparked drop of water after every step as it falls
+    //from one chunk into the one below
+    fn falling_spark(parallel: bool) -> Vec<Charge> {
+        let registry = MaterialRegistry::builtin();
+        let mut grid = SandGrid::with_seed(64, 64, 1);
+        grid.set_parallel(parallel);
+        grid.place_cells(
+            MaterialId::WATER,
+            &[(10, 29)],
+            &BrushFilter::default(),
+            &registry,
+        );
+        grid.update_sand(&registry, 0);
+
+        let water = |grid: &SandGrid| {
+            let i = grid
+                .grid
+                .iter()
+                .position(|particle| particle.sand_type == MaterialId::WATER)
+                .unwrap();
+            (i % grid.width, i / grid.width)
+        };
+        let (x, y) = water(&grid);
+        grid.spark(x, y, &registry);
+
+        let mut charges = Vec::new();
+        for frame in 1..6 {
+            grid.update_sand(&registry, frame);
+            let (x, y) = water(&grid);
+            charges.push(grid.get_charge(x, y));
+        }
+        assert!(water(&grid).1 >= CHUNK_SIZE);
+        charges
+    }
+
+    #[test]
+    fn spark_crosses_chunks() {
+        assert_eq!(falling_spark(true), falling_spark(false));
+    }
+}
@@ -0,0 +1,244 @@
+//Going back in time to see how the grid got the way it is
+//
+//Every few frames the grid is saved in the save file format and kept
+//in a ring buffer, the oldest snapshot is forgotten when it is full.
+//Scrubbing restores snapshots into the grid, recording again from an
+//earlier frame forgets every snapshot after it.
+
+use super::{material_registry::MaterialRegistry, sand_file::SandFileError, SandGrid};
+use std::collections::VecDeque;
+
+struct Snapshot {
+    frame: u32,
+    bytes: Vec<u8>,
+}
+
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    //Frames between snapshots
+    interval: u32,
+    //Most snapshots kept at once
+    capacity: usize,
+    //The snapshot the grid was restored from while scrubbing
+    position: Option<usize>,
+}
+
+impl Rewind {
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            position: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    //None unless a snapshot has been restored since the last one was taken
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    pub fn frame(&self, index: usize) -> Option<u32> {
+        self.snapshots.get(index).map(|snapshot| snapshot.frame)
+    }
+
+    //Call before updating the grid each frame, the grid is
+    //saved when the frame is a multiple of the interval
+    pub fn record(
+        &mut self,
+        frame: u32,
+        grid: &SandGrid,
+        registry: &MaterialRegistry,
+    ) -> Result<(), SandFileError> {
+        if !frame.is_multiple_of(self.interval) {
+            return Ok(());
+        }
+
+        self.save(frame, grid, registry).map(|_| ())
+    }
+
+    //Returns how many of the oldest snapshots were forgotten to make room,
+    //every index after them moves down by that much
+    fn save(
+        &mut self,
+        frame: u32,
+        grid: &SandGrid,
+        registry: &MaterialRegistry,
+    ) -> Result<usize, SandFileError> {
+        let bytes = grid.snapshot(registry)?;
+
+        self.position = None;
+        while self
+            .snapshots
+            .back()
+            .is_some_and(|snapshot| snapshot.frame >= frame)
+        {
+            self.snapshots.pop_back();
+        }
+        self.snapshots.push_back(Snapshot { frame, bytes });
+        let mut forgotten = 0;
+        while self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+            forgotten += 1;
+        }
+
+        Ok(forgotten)
+    }
+
+    //Restores a snapshot and returns the frame it was taken on, the grid
+    //as it is at frame is saved first so scrubbing can come back to it.
+    //index is the position of the snapshot before that save.
+    pub fn seek(
+        &mut self,
+        index: usize,
+        frame: u32,
+        grid: &mut SandGrid,
+        registry: &MaterialRegistry,
+    ) -> Result<Option<u32>, SandFileError> {
+        let mut index = index;
+        if self.position.is_none() {
+            let forgotten = self.save(frame, grid, registry)?;
+            //The snapshot asked for moved down, or was forgotten
+            //if it was the oldest, then the oldest left is used
+            index = index.saturating_sub(forgotten);
+        }
+
+        let Some(snapshot) = self.snapshots.get(index) else {
+            return Ok(None);
+        };
+
+        grid.read_into(snapshot.bytes.as_slice(), registry)?;
+        self.position = Some(index);
+        Ok(Some(snapshot.frame))
+    }
+
+    //Returns None if there is no earlier snapshot
+    pub fn step_back(
+        &mut self,
+        frame: u32,
+        grid: &mut SandGrid,
+        registry: &MaterialRegistry,
+    ) -> Result<Option<u32>, SandFileError> {
+        if self.position.is_none() {
+            self.save(frame, grid, registry)?;
+            self.position = Some(self.snapshots.len() - 1);
+        }
+
+        match self.position.and_then(|index| index.checked_sub(1)) {
+            Some(index) => self.seek(index, frame, grid, registry),
+            None => Ok(None),
+        }
+    }
+
+    //Returns None if there is no later snapshot
+    pub fn step_forward(
+        &mut self,
+        frame: u32,
+        grid: &mut SandGrid,
+        registry: &MaterialRegistry,
+    ) -> Result<Option<u32>, SandFileError> {
+        match self.position {
+            Some(index) => self.seek(index + 1, frame, grid, registry),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sand::material_registry::MaterialId;
+
+    //A grid with a wall in a different cell on every frame
+    fn grid_at(frame: u32) -> SandGrid {
+        let mut grid = SandGrid::with_seed(4, 4, 1);
+        grid.set_sand(frame as usize % 4, frame as usize / 4, MaterialId::WALL);
+        grid
+    }
+
+    fn assert_at(grid: &SandGrid, frame: u32) {
+        assert_eq!(
+            grid.get_sand(frame as usize % 4, frame as usize / 4),
+            MaterialId::WALL
+        );
+    }
+
+    //Snapshots of frames 0 to 4 have been taken, only 2 to 4 are left,
+    //and the grid is at frame 5
+    fn recorded(registry: &MaterialRegistry) -> Rewind {
+        let mut rewind = Rewind::new(1, 3);
+        for frame in 0..5 {
+            rewind.record(frame, &grid_at(frame), registry).unwrap();
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.frame(0), Some(2));
+        rewind
+    }
+
+    #[test]
+    fn seek_after_forgetting() {
+        let registry = MaterialRegistry::builtin();
+
+        //Saving frame 5 forgets frame 2, index 1 is still frame 3
+        let mut rewind = recorded(&registry);
+        let mut grid = grid_at(5);
+        let seeked = rewind.seek(1, 5, &mut grid, &registry).unwrap();
+        assert_eq!(seeked, Some(3));
+        assert_at(&grid, 3);
+        assert_eq!(rewind.position(), Some(0));
+
+        //The forgotten snapshot is replaced by the oldest one left
+        let mut rewind = recorded(&registry);
+        let mut grid = grid_at(5);
+        let seeked = rewind.seek(0, 5, &mut grid, &registry).unwrap();
+        assert_eq!(seeked, Some(3));
+        assert_at(&grid, 3);
+    }
+
+    #[test]
+    fn step_back_and_forward() {
+        let registry = MaterialRegistry::builtin();
+        let mut rewind = recorded(&registry);
+        let mut grid = grid_at(5);
+
+        for frame in [4, 3] {
+            let stepped = rewind.step_back(5, &mut grid, &registry).unwrap();
+            assert_eq!(stepped, Some(frame));
+            assert_at(&grid, frame);
+        }
+        assert_eq!(rewind.step_back(5, &mut grid, &registry).unwrap(), None);
+
+        //Stepping forward ends at the frame the grid was at before scrubbing
+        for frame in [4, 5] {
+            let stepped = rewind.step_forward(5, &mut grid, &registry).unwrap();
+            assert_eq!(stepped, Some(frame));
+            assert_at(&grid, frame);
+        }
+        assert_eq!(rewind.step_forward(5, &mut grid, &registry).unwrap(), None);
+        assert_eq!(rewind.len(), 3);
+    }
+
+    #[test]
+    fn record_after_seek() {
+        let registry = MaterialRegistry::builtin();
+        let mut rewind = recorded(&registry);
+        let mut grid = grid_at(5);
+        rewind.seek(1, 5, &mut grid, &registry).unwrap();
+
+        //Carrying on from frame 3 forgets the snapshots of frames 4 and 5
+        rewind.record(4, &grid, &registry).unwrap();
+        assert_eq!(rewind.position(), None);
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.frame(0), Some(3));
+        assert_eq!(rewind.frame(1), Some(4));
+        assert_eq!(rewind.step_forward(4, &mut grid, &registry).unwrap(), None);
+    }
+}
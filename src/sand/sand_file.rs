@@ -0,0 +1,509 @@
+//Binary save format for sand grids
+//
+//Layout (all integers are little endian):
+//  magic      4 bytes  "SPNT"
+//  version    u8
+//  width      u32
+//  height     u32
+//  materials  count: u8, then (id: u8, name length: u16, name: utf-8)
+//             for every material in the grid, ids in the file are
+//             matched to the registry by name when loading
+//  runs       (length: u32, id: u8) repeated until width * height
+//             cells have been described, cells are stored row by row
+//  heat       (length: u32, temperature: f32) runs in the same order
+//  cloned     (length: u32, id: u8) runs in the same order, the material
+//             each cloner copies, Air for cells that have not copied anything
+//
+//Version 1 files have no material table and use the ids of the
+//built-in materials, versions 1 and 2 have no temperatures and
+//cells start at the temperature of their material, versions before
+//4 have no cloned materials
+
+use super::{
+    material_registry::{MaterialId, MaterialRegistry, BUILTIN_MATERIALS},
+    SandGrid, SandParticle,
+};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: [u8; 4] = *b"SPNT";
+const VERSION: u8 = 4;
+//Largest grid a file can describe, 4096x4096, so a corrupt header
+//can not make loading allocate gigabytes
+const MAX_CELLS: usize = 1 << 24;
+
+#[derive(Debug)]
+pub enum SandFileError {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    //The file ended before all cells were read
+    Truncated,
+    //A run uses an id that is not in the material table
+    UnknownMaterial(u8),
+    //The file uses a material that is not in the registry
+    UnknownMaterialName(String),
+    InvalidDimensions(u32, u32),
+    //A run of cells extends past the end of the grid
+    InvalidRun,
+    //A temperature in the file is NaN or infinite
+    InvalidTemperature(f32),
+    DimensionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+}
+
+impl fmt::Display for SandFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::BadMagic => write!(f, "not a sandpaint file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported file version {version}")
+            }
+            Self::Truncated => write!(f, "file is truncated"),
+            Self::UnknownMaterial(id) => write!(f, "unknown material id {id}"),
+            Self::UnknownMaterialName(name) => write!(f, "unknown material \"{name}\""),
+            Self::InvalidDimensions(w, h) => write!(f, "invalid grid dimensions {w}x{h}"),
+            Self::InvalidRun => write!(f, "run of cells extends past the end of the grid"),
+            Self::InvalidTemperature(temperature) => {
+                write!(f, "invalid temperature {temperature}")
+            }
+            Self::DimensionMismatch { expected, found } => write!(
+                f,
+                "expected a {}x{} grid, found {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SandFileError {}
+
+impl From<std::io::Error> for SandFileError {
+    fn from(err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            return Self::Truncated;
+        }
+
+        Self::Io(err)
+    }
+}
+
+//Writes (length: u32, value) pairs for every run of equal values
+fn write_runs<W: Write, T: PartialEq + Copy>(
+    writer: &mut W,
+    mut values: impl Iterator<Item = T>,
+    write_value: impl Fn(&mut W, T) -> std::io::Result<()>,
+) -> Result<(), SandFileError> {
+    let mut current = match values.next() {
+        Some(value) => value,
+        _ => return Ok(()),
+    };
+    let mut length = 1u32;
+    for value in values {
+        if value == current && length < u32::MAX {
+            length += 1;
+            continue;
+        }
+
+        writer.write_all(&length.to_le_bytes())?;
+        write_value(writer, current)?;
+        current = value;
+        length = 1;
+    }
+    writer.write_all(&length.to_le_bytes())?;
+    write_value(writer, current)?;
+
+    Ok(())
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, SandFileError> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16, SandFileError> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, SandFileError> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+impl SandGrid {
+    pub fn write_to<W: Write>(
+        &self,
+        mut writer: W,
+        registry: &MaterialRegistry,
+    ) -> Result<(), SandFileError> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(self.width as u32).to_le_bytes())?;
+        writer.write_all(&(self.height as u32).to_le_bytes())?;
+
+        let mut used = [false; 256];
+        for particle in &self.grid {
+            used[particle.sand_type.0 as usize] = true;
+        }
+        for sand in &self.cloned {
+            used[sand.0 as usize] = true;
+        }
+        let used_ids: Vec<MaterialId> = registry.ids().filter(|id| used[id.0 as usize]).collect();
+        writer.write_all(&[used_ids.len() as u8])?;
+        for id in used_ids {
+            let name = registry.name(id).as_bytes();
+            writer.write_all(&[id.0])?;
+            writer.write_all(&(name.len() as u16).to_le_bytes())?;
+            writer.write_all(name)?;
+        }
+
+        write_runs(
+            &mut writer,
+            self.grid.iter().map(|particle| particle.sand_type),
+            |writer, sand| writer.write_all(&[sand.0]),
+        )?;
+        write_runs(
+            &mut writer,
+            self.temperature
+                .iter()
+                .map(|temperature| temperature.to_bits()),
+            |writer, bits| writer.write_all(&bits.to_le_bytes()),
+        )?;
+        write_runs(&mut writer, self.cloned.iter().copied(), |writer, sand| {
+            writer.write_all(&[sand.0])
+        })?;
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(
+        mut reader: R,
+        registry: &MaterialRegistry,
+    ) -> Result<Self, SandFileError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(SandFileError::BadMagic);
+        }
+
+        let version = read_u8(&mut reader)?;
+        if version == 0 || version > VERSION {
+            return Err(SandFileError::UnsupportedVersion(version));
+        }
+
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let cell_count = (width as usize)
+            .checked_mul(height as usize)
+            .filter(|count| *count > 0 && *count <= MAX_CELLS)
+            .ok_or(SandFileError::InvalidDimensions(width, height))?;
+
+        //Maps ids in the file to ids in the registry
+        let mut materials = [None; 256];
+        if version == 1 {
+            //Explosion was never placeable so it can not be in a grid
+            for (id, name) in BUILTIN_MATERIALS.iter().enumerate() {
+                if MaterialId(id as u8) != MaterialId::EXPLOSION {
+                    materials[id] = registry.id(name);
+                }
+            }
+        } else {
+            let count = read_u8(&mut reader)?;
+            for _ in 0..count {
+                let id = read_u8(&mut reader)?;
+                let mut name = vec![0u8; read_u16(&mut reader)? as usize];
+                reader.read_exact(&mut name)?;
+                let name = String::from_utf8_lossy(&name).to_string();
+                materials[id as usize] = Some(
+                    registry
+                        .id(&name)
+                        .ok_or(SandFileError::UnknownMaterialName(name))?,
+                );
+            }
+        }
+
+        //Cells are only allocated as runs are read so that a corrupt
+        //header can not make us allocate a huge grid up front
+        let mut grid = Vec::<SandParticle>::new();
+        while grid.len() < cell_count {
+            let length = read_u32(&mut reader)? as usize;
+            let id = read_u8(&mut reader)?;
+            let sand = materials[id as usize].ok_or(SandFileError::UnknownMaterial(id))?;
+
+            if length == 0 || length > cell_count - grid.len() {
+                return Err(SandFileError::InvalidRun);
+            }
+
+            let particle = SandParticle::new(sand, true);
+            grid.resize(grid.len() + length, particle);
+        }
+
+        let mut sand_grid =
+            SandGrid::from_particles(width as usize, height as usize, grid, rand::random());
+
+        if version < 3 {
+            for i in 0..cell_count {
+                sand_grid.temperature[i] =
+                    registry.initial_temperature(sand_grid.grid[i].sand_type);
+            }
+            return Ok(sand_grid);
+        }
+
+        let mut pos = 0;
+        while pos < cell_count {
+            let length = read_u32(&mut reader)? as usize;
+            let temperature = f32::from_bits(read_u32(&mut reader)?);
+
+            if length == 0 || length > cell_count - pos {
+                return Err(SandFileError::InvalidRun);
+            }
+            if !temperature.is_finite() {
+                return Err(SandFileError::InvalidTemperature(temperature));
+            }
+
+            sand_grid.temperature[pos..(pos + length)].fill(temperature);
+            pos += length;
+        }
+
+        if version < 4 {
+            return Ok(sand_grid);
+        }
+
+        let mut pos = 0;
+        while pos < cell_count {
+            let length = read_u32(&mut reader)? as usize;
+            let id = read_u8(&mut reader)?;
+            let sand = materials[id as usize].ok_or(SandFileError::UnknownMaterial(id))?;
+
+            if length == 0 || length > cell_count - pos {
+                return Err(SandFileError::InvalidRun);
+            }
+
+            sand_grid.cloned[pos..(pos + length)].fill(sand);
+            pos += length;
+        }
+
+        Ok(sand_grid)
+    }
+
+    pub fn save<P: AsRef<Path>>(
+        &self,
+        path: P,
+        registry: &MaterialRegistry,
+    ) -> Result<(), SandFileError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer, registry)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        registry: &MaterialRegistry,
+    ) -> Result<Self, SandFileError> {
+        Self::read_from(BufReader::new(File::open(path)?), registry)
+    }
+
+    //Replaces the contents of this grid with a saved grid,
+    //the saved grid must have the same dimensions
+    pub fn load_into<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        registry: &MaterialRegistry,
+    ) -> Result<(), SandFileError> {
+        self.read_into(BufReader::new(File::open(path)?), registry)
+    }
+
+    //The grid in the save format kept in memory, for going back to later
+    pub(crate) fn snapshot(&self, registry: &MaterialRegistry) -> Result<Vec<u8>, SandFileError> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes, registry)?;
+        Ok(bytes)
+    }
+
+    pub fn read_into<R: Read>(
+        &mut self,
+        reader: R,
+        registry: &MaterialRegistry,
+    ) -> Result<(), SandFileError> {
+        let loaded = Self::read_from(reader, registry)?;
+
+        if (loaded.width, loaded.height) != (self.width, self.height) {
+            return Err(SandFileError::DimensionMismatch {
+                expected: (self.width, self.height),
+                found: (loaded.width, loaded.height),
+            });
+        }
+
+        self.grid = loaded.grid;
+        self.temperature = loaded.temperature;
+        self.cloned = loaded.cloned;
+        self.chunks = loaded.chunks;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //The start of a file up to the material table
+    fn header(version: u8, width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(version);
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes
+    }
+
+    fn push_run(bytes: &mut Vec<u8>, length: u32, id: u8) {
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.push(id);
+    }
+
+    fn saved_grid(registry: &MaterialRegistry) -> SandGrid {
+        let mut grid = SandGrid::with_seed(8, 6, 1);
+        grid.place_sand(MaterialId::SAND, 2, 2, 1, registry);
+        grid.place_sand(MaterialId::WATER, 6, 4, 1, registry);
+        grid.set_temperature(0, 0, 500.0);
+        grid.cloned[5] = MaterialId::OIL;
+        grid
+    }
+
+    #[test]
+    fn round_trip() {
+        let registry = MaterialRegistry::builtin();
+        let grid = saved_grid(&registry);
+        let mut bytes = Vec::new();
+        grid.write_to(&mut bytes, &registry).unwrap();
+
+        let loaded = SandGrid::read_from(bytes.as_slice(), &registry).unwrap();
+        assert_eq!((loaded.width, loaded.height), (8, 6));
+        for i in 0..grid.grid.len() {
+            assert_eq!(loaded.grid[i].sand_type, grid.grid[i].sand_type);
+        }
+        assert_eq!(loaded.temperature, grid.temperature);
+        assert_eq!(loaded.cloned, grid.cloned);
+    }
+
+    #[test]
+    fn truncated() {
+        let registry = MaterialRegistry::builtin();
+        let mut bytes = Vec::new();
+        saved_grid(&registry)
+            .write_to(&mut bytes, &registry)
+            .unwrap();
+        bytes.pop();
+
+        let result = SandGrid::read_from(bytes.as_slice(), &registry);
+        assert!(matches!(result, Err(SandFileError::Truncated)));
+    }
+
+    #[test]
+    fn unknown_material() {
+        let registry = MaterialRegistry::builtin();
+        let mut bytes = header(2, 2, 2);
+        //An empty material table
+        bytes.push(0);
+        push_run(&mut bytes, 4, MaterialId::SAND.0);
+
+        let result = SandGrid::read_from(bytes.as_slice(), &registry);
+        assert!(matches!(result, Err(SandFileError::UnknownMaterial(1))));
+    }
+
+    #[test]
+    fn invalid_run() {
+        let registry = MaterialRegistry::builtin();
+        for length in [0, 5] {
+            let mut bytes = header(1, 2, 2);
+            push_run(&mut bytes, length, MaterialId::SAND.0);
+
+            let result = SandGrid::read_from(bytes.as_slice(), &registry);
+            assert!(matches!(result, Err(SandFileError::InvalidRun)));
+        }
+    }
+
+    //A short file claiming a huge grid is rejected before anything is allocated
+    #[test]
+    fn huge_grid() {
+        let registry = MaterialRegistry::builtin();
+        let mut bytes = header(1, 65536, 65536);
+        push_run(&mut bytes, u32::MAX, MaterialId::SAND.0);
+
+        let result = SandGrid::read_from(bytes.as_slice(), &registry);
+        assert!(matches!(
+            result,
+            Err(SandFileError::InvalidDimensions(65536, 65536))
+        ));
+    }
+
+    #[test]
+    fn non_finite_temperature() {
+        let registry = MaterialRegistry::builtin();
+        for temperature in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let mut bytes = header(3, 2, 2);
+            bytes.push(1);
+            bytes.push(MaterialId::SAND.0);
+            bytes.extend_from_slice(&4u16.to_le_bytes());
+            bytes.extend_from_slice(b"Sand");
+            push_run(&mut bytes, 4, MaterialId::SAND.0);
+            bytes.extend_from_slice(&4u32.to_le_bytes());
+            bytes.extend_from_slice(&temperature.to_le_bytes());
+
+            let result = SandGrid::read_from(bytes.as_slice(), &registry);
+            assert!(matches!(result, Err(SandFileError::InvalidTemperature(_))));
+        }
+    }
+
+    #[test]
+    fn dimension_mismatch() {
+        let registry = MaterialRegistry::builtin();
+        let path = std::env::temp_dir().join(format!(
+            "sandpaint-dimension-mismatch-{}.sand",
+            std::process::id()
+        ));
+        SandGrid::with_seed(4, 4, 1).save(&path, &registry).unwrap();
+
+        let mut grid = SandGrid::with_seed(8, 8, 1);
+        let result = grid.load_into(&path, &registry);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            result,
+            Err(SandFileError::DimensionMismatch {
+                expected: (8, 8),
+                found: (4, 4),
+            })
+        ));
+    }
+
+    //Files from before the material table use the built-in ids
+    #[test]
+    fn version_1() {
+        let registry = MaterialRegistry::builtin();
+        let mut bytes = header(1, 2, 2);
+        push_run(&mut bytes, 2, MaterialId::SAND.0);
+        push_run(&mut bytes, 2, MaterialId::WATER.0);
+
+        let grid = SandGrid::read_from(bytes.as_slice(), &registry).unwrap();
+        let sand = registry.id("Sand").unwrap();
+        let water = registry.id("Water").unwrap();
+        assert_eq!(grid.get_sand(0, 0), sand);
+        assert_eq!(grid.get_sand(1, 0), sand);
+        assert_eq!(grid.get_sand(0, 1), water);
+        assert_eq!(grid.get_sand(1, 1), water);
+        assert_eq!(
+            grid.get_temperature(0, 1),
+            registry.initial_temperature(water)
+        );
+        assert!(grid.cloned.iter().all(|sand| *sand == MaterialId::AIR));
+    }
+}
@@ -0,0 +1,266 @@
+//Exporting grids as PNG images and importing them back,
+//every cell is one pixel coloured with the material colour
+
+use super::{
+    material_registry::{MaterialId, MaterialRegistry},
+    SandGrid, SandParticle,
+};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+//Pixels that are at least this transparent become air
+const MIN_ALPHA: u8 = 128;
+
+#[derive(Debug)]
+pub enum SandImageError {
+    Io(std::io::Error),
+    Decoding(png::DecodingError),
+    Encoding(png::EncodingError),
+}
+
+impl fmt::Display for SandImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Decoding(err) => write!(f, "{err}"),
+            Self::Encoding(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for SandImageError {}
+
+impl From<std::io::Error> for SandImageError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<png::DecodingError> for SandImageError {
+    fn from(err: png::DecodingError) -> Self {
+        Self::Decoding(err)
+    }
+}
+
+impl From<png::EncodingError> for SandImageError {
+    fn from(err: png::EncodingError) -> Self {
+        Self::Encoding(err)
+    }
+}
+
+fn color_distance_squared(color1: (u8, u8, u8), color2: (u8, u8, u8)) -> u32 {
+    let dr = color1.0 as i32 - color2.0 as i32;
+    let dg = color1.1 as i32 - color2.1 as i32;
+    let db = color1.2 as i32 - color2.2 as i32;
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+//Returns the material with the closest colour, if no material
+//is within tolerance (distance in RGB space) then it returns air.
+//Hidden materials can not be placed so colours closest to one of
+//them, like an exported Burning Fuse, also return air.
+pub fn nearest_sand(
+    registry: &MaterialRegistry,
+    color: (u8, u8, u8),
+    tolerance: u32,
+) -> MaterialId {
+    let nearest = registry
+        .ids()
+        .filter(|sand| registry.get_sand_property(*sand).is_some())
+        .map(|sand| {
+            let sand_color = registry.sand_rgb(sand);
+            (sand, color_distance_squared(color, sand_color))
+        })
+        .min_by_key(|(_, distance)| *distance);
+
+    match nearest {
+        Some((sand, distance))
+            if distance <= tolerance.saturating_mul(tolerance)
+                && registry.get(sand).is_some_and(|material| !material.hidden) =>
+        {
+            sand
+        }
+        _ => MaterialId::AIR,
+    }
+}
+
+impl SandGrid {
+    pub fn write_png<W: Write>(
+        &self,
+        writer: W,
+        registry: &MaterialRegistry,
+    ) -> Result<(), SandImageError> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut data = Vec::with_capacity(self.grid.len() * 3);
+        for particle in &self.grid {
+            let (r, g, b) = registry.sand_rgb(particle.sand_type);
+            data.extend_from_slice(&[r, g, b]);
+        }
+
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(&data)?;
+        png_writer.finish()?;
+
+        Ok(())
+    }
+
+    pub fn read_png<R: Read>(
+        reader: R,
+        registry: &MaterialRegistry,
+        tolerance: u32,
+    ) -> Result<Self, SandImageError> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut png_reader = decoder.read_info()?;
+        let mut data = vec![0u8; png_reader.output_buffer_size()];
+        let info = png_reader.next_frame(&mut data)?;
+
+        let width = info.width as usize;
+        let height = info.height as usize;
+        let mut grid = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = &data[(y * info.line_size)..((y + 1) * info.line_size)];
+            for x in 0..width {
+                let (color, alpha) = match info.color_type {
+                    png::ColorType::Grayscale => ((row[x], row[x], row[x]), u8::MAX),
+                    png::ColorType::GrayscaleAlpha => {
+                        ((row[x * 2], row[x * 2], row[x * 2]), row[x * 2 + 1])
+                    }
+                    png::ColorType::Rgba => {
+                        ((row[x * 4], row[x * 4 + 1], row[x * 4 + 2]), row[x * 4 + 3])
+                    }
+                    _ => ((row[x * 3], row[x * 3 + 1], row[x * 3 + 2]), u8::MAX),
+                };
+
+                let sand = if alpha < MIN_ALPHA {
+                    MaterialId::AIR
+                } else {
+                    nearest_sand(registry, color, tolerance)
+                };
+
+                grid.push(SandParticle::new(sand, true));
+            }
+        }
+
+        let mut sand_grid = SandGrid::from_particles(width, height, grid, rand::random());
+        for i in 0..sand_grid.grid.len() {
+            sand_grid.temperature[i] = registry.initial_temperature(sand_grid.grid[i].sand_type);
+        }
+
+        Ok(sand_grid)
+    }
+
+    pub fn export_png<P: AsRef<Path>>(
+        &self,
+        path: P,
+        registry: &MaterialRegistry,
+    ) -> Result<(), SandImageError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_png(&mut writer, registry)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    //Each pixel is turned into the material with the nearest colour,
+    //pixels further than tolerance from every material or nearest to
+    //a hidden material become air
+    pub fn import_png<P: AsRef<Path>>(
+        path: P,
+        registry: &MaterialRegistry,
+        tolerance: u32,
+    ) -> Result<Self, SandImageError> {
+        Self::read_png(BufReader::new(File::open(path)?), registry, tolerance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(grid: &SandGrid, registry: &MaterialRegistry, tolerance: u32) -> SandGrid {
+        let mut bytes = Vec::new();
+        grid.write_png(&mut bytes, registry).unwrap();
+        SandGrid::read_png(bytes.as_slice(), registry, tolerance).unwrap()
+    }
+
+    //One pixel image
+    fn pixel(color_type: png::ColorType, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 1, 1);
+        encoder.set_color(color_type);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn visible_round_trip() {
+        let registry = MaterialRegistry::builtin();
+        let placeable: Vec<MaterialId> = registry.placeable().collect();
+        let mut grid = SandGrid::with_seed(placeable.len(), 1, 1);
+        for (x, sand) in placeable.iter().enumerate() {
+            grid.set_sand(x, 0, *sand);
+        }
+
+        let loaded = round_trip(&grid, &registry, 0);
+        assert_eq!((loaded.width, loaded.height), (placeable.len(), 1));
+        for (x, sand) in placeable.iter().enumerate() {
+            assert_eq!(loaded.get_sand(x, 0), *sand);
+            assert_eq!(
+                loaded.get_temperature(x, 0),
+                registry.initial_temperature(*sand)
+            );
+        }
+    }
+
+    #[test]
+    fn hidden_becomes_air() {
+        let registry = MaterialRegistry::builtin();
+        let mut grid = SandGrid::with_seed(1, 1, 1);
+        grid.set_sand(0, 0, registry.id("Burning Fuse").unwrap());
+
+        let loaded = round_trip(&grid, &registry, 64);
+        assert_eq!(loaded.get_sand(0, 0), MaterialId::AIR);
+    }
+
+    #[test]
+    fn tolerance() {
+        let registry = MaterialRegistry::builtin();
+        //Sand is (255, 200, 0)
+        assert_eq!(nearest_sand(&registry, (250, 195, 5), 10), MaterialId::SAND);
+        assert_eq!(nearest_sand(&registry, (250, 195, 5), 8), MaterialId::AIR);
+
+        let bytes = pixel(png::ColorType::Rgb, &[250, 195, 5]);
+        let grid = SandGrid::read_png(bytes.as_slice(), &registry, 10).unwrap();
+        assert_eq!(grid.get_sand(0, 0), MaterialId::SAND);
+        let grid = SandGrid::read_png(bytes.as_slice(), &registry, 8).unwrap();
+        assert_eq!(grid.get_sand(0, 0), MaterialId::AIR);
+    }
+
+    #[test]
+    fn alpha() {
+        let registry = MaterialRegistry::builtin();
+        for (alpha, sand) in [
+            (255, MaterialId::WALL),
+            (MIN_ALPHA, MaterialId::WALL),
+            (MIN_ALPHA - 1, MaterialId::AIR),
+            (0, MaterialId::AIR),
+        ] {
+            //Wall is (128, 128, 128)
+            let bytes = pixel(png::ColorType::Rgba, &[128, 128, 128, alpha]);
+            let grid = SandGrid::read_png(bytes.as_slice(), &registry, 0).unwrap();
+            assert_eq!(grid.get_sand(0, 0), sand);
+
+            let bytes = pixel(png::ColorType::GrayscaleAlpha, &[128, alpha]);
+            let grid = SandGrid::read_png(bytes.as_slice(), &registry, 0).unwrap();
+            assert_eq!(grid.get_sand(0, 0), sand);
+        }
+    }
+}
@@ -1,4 +1,26 @@
-use super::{sand_properties::SandProperties, Sand, SandGrid};
+use super::{material_registry::MaterialId, sand_properties::SandProperties, SandGrid};
+
+//Moves the particle at (x1, y1) into (x2, y2) and leaves air behind,
+//the temperature and charge of the particle move with it
+fn move_particle(
+    x1: usize,
+    y1: usize,
+    x2: usize,
+    y2: usize,
+    sand_grid: &mut SandGrid,
+    properties: &SandProperties,
+) {
+    sand_grid.set_sand(
+        x2,
+        y2,
+        properties.replace(sand_grid.get_sand(x1, y1), sand_grid.get_sand(x2, y2)),
+    );
+    sand_grid.set_sand(x1, y1, MaterialId::AIR);
+    sand_grid.swap_temperature(x1, y1, x2, y2);
+    sand_grid.move_charge(x1, y1, x2, y2);
+    sand_grid.set_updated(x2, y2);
+    sand_grid.set_updated(x1, y1);
+}
 
 pub fn swap(
     x1: usize,
@@ -14,10 +36,23 @@ pub fn swap(
         return false;
     }
 
-    if properties.can_sink_in.contains(&sand_grid.get_sand(x2, y2)) && rand::random() {
+    //Moving up swaps with heavier fluids, any other direction with lighter ones
+    let displace = if y2 < y1 {
+        &properties.rise_into
+    } else {
+        &properties.sink_into
+    };
+    let chance = match displace.get(sand_grid.get_sand(x2, y2)) {
+        Some(chance) => chance,
+        _ => return false,
+    };
+
+    if sand_grid.random::<f64>() < chance {
         let sand = sand_grid.get_sand(x2, y2);
         sand_grid.set_sand(x2, y2, sand_grid.get_sand(x1, y1));
         sand_grid.set_sand(x1, y1, sand);
+        sand_grid.swap_temperature(x1, y1, x2, y2);
+        sand_grid.swap_charge(x1, y1, x2, y2);
         sand_grid.set_updated(x1, y1);
         sand_grid.set_updated(x2, y2);
         return true;
@@ -43,14 +78,7 @@ pub fn fall_down(
     }
 
     if sand_grid.space_available(x, y + 1, properties) {
-        sand_grid.set_sand(
-            x,
-            y + 1,
-            properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x, y + 1)),
-        );
-        sand_grid.set_sand(x, y, Sand::Air);
-        sand_grid.set_updated(x, y + 1);
-        sand_grid.set_updated(x, y);
+        move_particle(x, y, x, y + 1, sand_grid, properties);
         return true;
     }
 
@@ -61,6 +89,12 @@ pub fn fall_down(
     false
 }
 
+//Whether a particle in row y can slide diagonally down into column to_x,
+//the cells under the one it moves into have to be empty down to its repose
+pub fn can_slide(y: usize, to_x: usize, sand_grid: &SandGrid, properties: &SandProperties) -> bool {
+    (2..=properties.repose).all(|dy| sand_grid.space_available(to_x, y + dy, properties))
+}
+
 #[allow(clippy::if_same_then_else)]
 pub fn fall_left_right(
     x: usize,
@@ -76,26 +110,18 @@ pub fn fall_left_right(
         return false;
     }
 
-    if rand::random() {
-        if x > 0 && sand_grid.space_available(x - 1, y + 1, properties) {
-            sand_grid.set_sand(
-                x - 1,
-                y + 1,
-                properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x - 1, y + 1)),
-            );
-            sand_grid.set_sand(x, y, Sand::Air);
-            sand_grid.set_updated(x - 1, y + 1);
-            sand_grid.set_updated(x, y);
-            return true;
-        } else if x < sand_grid.width - 1 && sand_grid.space_available(x + 1, y + 1, properties) {
-            sand_grid.set_sand(
-                x + 1,
-                y + 1,
-                properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x + 1, y + 1)),
-            );
-            sand_grid.set_sand(x, y, Sand::Air);
-            sand_grid.set_updated(x + 1, y + 1);
-            sand_grid.set_updated(x, y);
+    if sand_grid.random() {
+        if x > 0
+            && sand_grid.space_available(x - 1, y + 1, properties)
+            && can_slide(y, x - 1, sand_grid, properties)
+        {
+            move_particle(x, y, x - 1, y + 1, sand_grid, properties);
+            return true;
+        } else if x < sand_grid.width - 1
+            && sand_grid.space_available(x + 1, y + 1, properties)
+            && can_slide(y, x + 1, sand_grid, properties)
+        {
+            move_particle(x, y, x + 1, y + 1, sand_grid, properties);
             return true;
         }
 
@@ -105,35 +131,27 @@ pub fn fall_left_right(
             return true;
         }
     } else {
-        if x < sand_grid.width - 1 && sand_grid.space_available(x + 1, y + 1, properties) {
-            sand_grid.set_sand(
-                x + 1,
-                y + 1,
-                properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x + 1, y + 1)),
-            );
-            sand_grid.set_sand(x, y, Sand::Air);
-            sand_grid.set_updated(x + 1, y + 1);
-            sand_grid.set_updated(x, y);
-            return true;
-        } else if x > 0 && sand_grid.space_available(x - 1, y + 1, properties) {
-            sand_grid.set_sand(
-                x - 1,
-                y + 1,
-                properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x - 1, y + 1)),
-            );
-            sand_grid.set_sand(x, y, Sand::Air);
-            sand_grid.set_updated(x - 1, y + 1);
-            sand_grid.set_updated(x, y);
+        if x < sand_grid.width - 1
+            && sand_grid.space_available(x + 1, y + 1, properties)
+            && can_slide(y, x + 1, sand_grid, properties)
+        {
+            move_particle(x, y, x + 1, y + 1, sand_grid, properties);
+            return true;
+        } else if x > 0
+            && sand_grid.space_available(x - 1, y + 1, properties)
+            && can_slide(y, x - 1, sand_grid, properties)
+        {
+            move_particle(x, y, x - 1, y + 1, sand_grid, properties);
             return true;
         }
 
         if swap(x, y, x + 1, y + 1, sand_grid, properties) {
-            if sand_grid.get_sand(x + 1, y) == Sand::Air {
+            if sand_grid.get_sand(x + 1, y) == MaterialId::AIR {
                 swap(x + 1, y, x, y, sand_grid, properties);
             }
             return true;
         } else if x > 0 && swap(x, y, x - 1, y + 1, sand_grid, properties) {
-            if sand_grid.get_sand(x - 1, y) == Sand::Air {
+            if sand_grid.get_sand(x - 1, y) == MaterialId::AIR {
                 swap(x - 1, y, x, y, sand_grid, properties);
             }
             return true;
@@ -154,26 +172,12 @@ pub fn flow_left_right(
         return false;
     }
 
-    if rand::random() {
+    if sand_grid.random() {
         if x > 0 && sand_grid.space_available(x - 1, y, properties) {
-            sand_grid.set_sand(
-                x - 1,
-                y,
-                properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x - 1, y)),
-            );
-            sand_grid.set_sand(x, y, Sand::Air);
-            sand_grid.set_updated(x - 1, y);
-            sand_grid.set_updated(x, y);
+            move_particle(x, y, x - 1, y, sand_grid, properties);
             return true;
         } else if x < sand_grid.width - 1 && sand_grid.space_available(x + 1, y, properties) {
-            sand_grid.set_sand(
-                x + 1,
-                y,
-                properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x + 1, y)),
-            );
-            sand_grid.set_sand(x, y, Sand::Air);
-            sand_grid.set_updated(x + 1, y);
-            sand_grid.set_updated(x, y);
+            move_particle(x, y, x + 1, y, sand_grid, properties);
             return true;
         }
 
@@ -184,24 +188,10 @@ pub fn flow_left_right(
         }
     } else {
         if x < sand_grid.width - 1 && sand_grid.space_available(x + 1, y, properties) {
-            sand_grid.set_sand(
-                x + 1,
-                y,
-                properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x + 1, y)),
-            );
-            sand_grid.set_sand(x, y, Sand::Air);
-            sand_grid.set_updated(x + 1, y);
-            sand_grid.set_updated(x, y);
+            move_particle(x, y, x + 1, y, sand_grid, properties);
             return true;
         } else if x > 0 && sand_grid.space_available(x - 1, y, properties) {
-            sand_grid.set_sand(
-                x - 1,
-                y,
-                properties.replace(sand_grid.get_sand(x, y), sand_grid.get_sand(x - 1, y)),
-            );
-            sand_grid.set_sand(x, y, Sand::Air);
-            sand_grid.set_updated(x - 1, y);
-            sand_grid.set_updated(x, y);
+            move_particle(x, y, x - 1, y, sand_grid, properties);
             return true;
         }
 
@@ -214,3 +204,74 @@ pub fn flow_left_right(
 
     false
 }
+
+//Returns true if it can move up,
+//false otherwise
+pub fn rise_up(x: usize, y: usize, sand_grid: &mut SandGrid, properties: &SandProperties) -> bool {
+    if sand_grid.get_updated(x, y) {
+        return false;
+    }
+
+    if y == 0 {
+        return false;
+    }
+
+    if sand_grid.space_available(x, y - 1, properties) {
+        move_particle(x, y, x, y - 1, sand_grid, properties);
+        return true;
+    }
+
+    if swap(x, y, x, y - 1, sand_grid, properties) {
+        return true;
+    }
+
+    false
+}
+
+#[allow(clippy::if_same_then_else)]
+pub fn rise_left_right(
+    x: usize,
+    y: usize,
+    sand_grid: &mut SandGrid,
+    properties: &SandProperties,
+) -> bool {
+    if sand_grid.get_updated(x, y) {
+        return false;
+    }
+
+    if y == 0 {
+        return false;
+    }
+
+    if sand_grid.random() {
+        if x > 0 && sand_grid.space_available(x - 1, y - 1, properties) {
+            move_particle(x, y, x - 1, y - 1, sand_grid, properties);
+            return true;
+        } else if x < sand_grid.width - 1 && sand_grid.space_available(x + 1, y - 1, properties) {
+            move_particle(x, y, x + 1, y - 1, sand_grid, properties);
+            return true;
+        }
+
+        if x > 0 && swap(x, y, x - 1, y - 1, sand_grid, properties) {
+            return true;
+        } else if swap(x, y, x + 1, y - 1, sand_grid, properties) {
+            return true;
+        }
+    } else {
+        if x < sand_grid.width - 1 && sand_grid.space_available(x + 1, y - 1, properties) {
+            move_particle(x, y, x + 1, y - 1, sand_grid, properties);
+            return true;
+        } else if x > 0 && sand_grid.space_available(x - 1, y - 1, properties) {
+            move_particle(x, y, x - 1, y - 1, sand_grid, properties);
+            return true;
+        }
+
+        if swap(x, y, x + 1, y - 1, sand_grid, properties) {
+            return true;
+        } else if x > 0 && swap(x, y, x - 1, y - 1, sand_grid, properties) {
+            return true;
+        }
+    }
+
+    false
+}
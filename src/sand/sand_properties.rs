@@ -1,202 +1,298 @@
-use super::Sand;
-use std::collections::{HashMap, HashSet};
+use super::{material_registry::MaterialId, temperature::AMBIENT_TEMPERATURE};
+use serde::Deserialize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Movement {
+    #[default]
+    Static,
+    Powder,
+    //Falls straight down without piling up
+    Fall,
+    Liquid,
+    //Rises and drifts sideways
+    Gas,
+}
 
-pub struct SandProperties {
-    pub can_replace: HashSet<Sand>,
-    pub replace_with: HashMap<Sand, Sand>,
-    pub can_sink_in: HashSet<Sand>,
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Behavior {
+    #[default]
+    None,
+    Fire,
+    Explosive,
+    //Copies the first material touching it into the empty cells around it
+    Cloner,
+    //Deletes moving particles that touch it
+    Void,
 }
 
-pub struct SandSimulationProperties(HashMap<Sand, SandProperties>);
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Electricity {
+    #[default]
+    None,
+    //Carries sparks to neighboring conductors
+    Conductor,
+    //Sparks every conductor next to it
+    Battery,
+}
 
-impl SandProperties {
-    pub fn empty() -> Self {
-        SandProperties {
-            can_replace: HashSet::<Sand>::new(),
-            replace_with: HashMap::<Sand, Sand>::new(),
-            can_sink_in: HashSet::<Sand>::new(),
-        }
-    }
+//Set of materials with one bit for every material id
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct MaterialSet([u64; 4]);
 
-    pub fn from_vecs(
-        can_replace: Option<Vec<Sand>>,
-        replace_with: Option<Vec<(Sand, Sand)>>,
-        can_sink_in: Option<Vec<Sand>>,
-    ) -> Self {
-        let mut properties = Self::empty();
+impl MaterialSet {
+    pub fn insert(&mut self, sand: MaterialId) {
+        self.0[sand.0 as usize / 64] |= 1 << (sand.0 % 64);
+    }
 
-        properties.add_replaceable(Sand::Air);
+    pub fn contains(&self, sand: MaterialId) -> bool {
+        self.0[sand.0 as usize / 64] & (1 << (sand.0 % 64)) != 0
+    }
 
-        if let Some(can_replace) = can_replace {
-            can_replace
-                .into_iter()
-                .for_each(|sand| properties.add_replaceable(sand));
-        }
+    pub fn remove(&mut self, sand: MaterialId) {
+        self.0[sand.0 as usize / 64] &= !(1 << (sand.0 % 64));
+    }
 
-        if let Some(replace_with) = replace_with {
-            replace_with
-                .into_iter()
-                .for_each(|(sand1, sand2)| properties.add_replace_with(sand1, sand2));
-        }
+    pub fn is_empty(&self) -> bool {
+        self.0 == [0; 4]
+    }
+}
 
-        if let Some(can_sink_in) = can_sink_in {
-            can_sink_in
-                .into_iter()
-                .for_each(|sand| properties.add_can_sink_in(sand));
-        }
+//A value for some material ids, stored in a table indexed by id
+#[derive(Clone)]
+pub struct MaterialMap<T: Copy>(Box<[Option<T>; 256]>);
 
-        properties
+impl<T: Copy> Default for MaterialMap<T> {
+    fn default() -> Self {
+        Self(Box::new([None; 256]))
     }
+}
 
-    pub fn add_replaceable(&mut self, sand: Sand) {
-        self.can_replace.insert(sand);
+impl<T: Copy> MaterialMap<T> {
+    pub fn get(&self, sand: MaterialId) -> Option<T> {
+        self.0[sand.0 as usize]
     }
 
-    pub fn add_replace_with(&mut self, can_replace: Sand, replace_with: Sand) {
-        self.replace_with.insert(can_replace, replace_with);
+    pub fn contains_key(&self, sand: MaterialId) -> bool {
+        self.0[sand.0 as usize].is_some()
     }
 
-    pub fn add_can_sink_in(&mut self, sand: Sand) {
-        self.can_sink_in.insert(sand);
+    pub fn insert(&mut self, sand: MaterialId, value: T) {
+        self.0[sand.0 as usize] = Some(value);
     }
 
-    pub fn replace(&self, sand: Sand, sand_to_replace: Sand) -> Sand {
-        match self.replace_with.get(&sand_to_replace) {
-            Some(s) => *s,
-            _ => sand,
-        }
+    pub fn clear(&mut self) {
+        self.0.fill(None);
     }
 }
 
-impl SandSimulationProperties {
-    pub fn new() -> Self {
-        Self(HashMap::<Sand, SandProperties>::new())
-    }
+//Turn into another material when the number of neighbors
+//of a certain material is between min_count and max_count
+#[derive(Clone, Copy)]
+pub struct NeighborTransform {
+    pub neighbor: MaterialId,
+    pub turn_into: MaterialId,
+    pub min_count: u32,
+    pub max_count: u32,
+    pub probability: f64,
+}
 
-    pub fn simulation_sand_properties() -> Self {
-        let mut sand_sim_properties = Self::new();
+//Turn into another material when the temperature crosses a threshold
+#[derive(Clone, Copy)]
+pub struct PhaseChange {
+    pub temperature: f32,
+    pub turn_into: MaterialId,
+}
 
-        //Sand
-        {
-            let can_replace = vec![Sand::Fire];
-            let replace_with = vec![(Sand::Acid, Sand::Acid)];
-            let can_sink_in = vec![Sand::Water, Sand::Oil, Sand::Acid];
+//Turn into another material after a random number of steps,
+//on average the lifetime
+#[derive(Clone, Copy)]
+pub struct Decay {
+    pub lifetime: u32,
+    pub turn_into: MaterialId,
+}
 
-            let sand_property =
-                SandProperties::from_vecs(Some(can_replace), Some(replace_with), Some(can_sink_in));
-            sand_sim_properties.add_sand_property(Sand::Sand, sand_property);
-        }
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GrowDirection {
+    //Into any of the 4 neighbors
+    #[default]
+    Any,
+    //Straight down, only while hanging from something
+    Down,
+}
 
-        //Water
-        {
-            let can_replace = vec![Sand::Fire, Sand::Lava];
-            let replace_with = vec![(Sand::Lava, Sand::Stone)];
-            let can_sink_in = vec![Sand::Oil];
+//Spreading into neighboring air, the air turns into the material
+#[derive(Clone, Copy)]
+pub struct Growth {
+    //Chance of growing each step
+    pub probability: f64,
+    pub direction: GrowDirection,
+    //Air can only be grown into when it touches this material
+    pub needs: Option<MaterialId>,
+    //The material that was needed turns into air when it is grown next to
+    pub consumes: bool,
+}
 
-            let sand_property =
-                SandProperties::from_vecs(Some(can_replace), Some(replace_with), Some(can_sink_in));
-            sand_sim_properties.add_sand_property(Sand::Water, sand_property);
-        }
+//Turn into another material when resting on a material that
+//touches another one, like a seed sprouting on wet sand
+#[derive(Clone, Copy)]
+pub struct Germination {
+    pub on: MaterialId,
+    pub near: MaterialId,
+    pub turn_into: MaterialId,
+    pub probability: f64,
+}
 
-        //Wall
-        {
-            let sand_property = SandProperties::from_vecs(None, None, None);
-            sand_sim_properties.add_sand_property(Sand::Wall, sand_property);
-        }
+//How far and how hard an explosive material blows up, rays from the
+//explosion lose the hardness of every material they pass through
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Blast {
+    pub radius: usize,
+    pub energy: f32,
+}
 
-        //Wood
-        {
-            let sand_property = SandProperties::from_vecs(None, None, None);
-            sand_sim_properties.add_sand_property(Sand::Wood, sand_property);
+impl Default for Blast {
+    fn default() -> Self {
+        Self {
+            radius: 64,
+            energy: 100.0,
         }
+    }
+}
 
-        //Fire
-        {
-            let can_replace = vec![Sand::Oil, Sand::Wood];
-            let sand_property = SandProperties::from_vecs(Some(can_replace), None, None);
-            sand_sim_properties.add_sand_property(Sand::Fire, sand_property);
-        }
+//Burning along a line of a material at a fixed rate, every interval
+//steps the neighboring cells of that material catch and this cell
+//turns into something else
+#[derive(Clone, Copy)]
+pub struct Burn {
+    pub spreads_to: MaterialId,
+    pub interval: u32,
+    pub turn_into: MaterialId,
+}
 
-        //Oil
-        {
-            let can_replace = vec![Sand::Fire];
-            let sand_property = SandProperties::from_vecs(Some(can_replace), None, None);
-            sand_sim_properties.add_sand_property(Sand::Oil, sand_property);
-        }
+//Placing a material in the empty cells around the cell every interval steps
+#[derive(Clone, Copy)]
+pub struct Emit {
+    pub sand: MaterialId,
+    pub interval: u32,
+}
 
-        //Acid
-        {
-            let can_sink_in = vec![Sand::Water, Sand::Oil];
-            let can_replace = vec![Sand::Wood, Sand::Sand, Sand::Fire, Sand::Stone];
-
-            let replace_with = vec![
-                (Sand::Wood, Sand::Air),
-                (Sand::Sand, Sand::Air),
-                (Sand::Fire, Sand::Air),
-                (Sand::Stone, Sand::Air),
-            ];
-
-            let sand_property =
-                SandProperties::from_vecs(Some(can_replace), Some(replace_with), Some(can_sink_in));
-            sand_sim_properties.add_sand_property(Sand::Acid, sand_property);
-        }
+//What a fire leaves behind when it burns out
+#[derive(Clone, Copy)]
+pub struct BurnOut {
+    pub turn_into: MaterialId,
+    pub probability: f64,
+}
 
-        //Lava
-        {
-            let can_replace = vec![Sand::Water];
-            let replace_with = vec![(Sand::Water, Sand::Stone)];
-            let can_sink_in = vec![Sand::Water, Sand::Acid, Sand::Oil];
+pub struct SandProperties {
+    pub can_replace: MaterialSet,
+    pub replace_with: MaterialMap<MaterialId>,
+    //Heavier materials sink through lighter liquids and gases
+    pub density: f32,
+    //Chance of swapping places with a fluid when moving down or sideways
+    //into it, or up into it, derived from the densities of the materials
+    pub sink_into: MaterialMap<f64>,
+    pub rise_into: MaterialMap<f64>,
+    pub movement: Movement,
+    //Empty cells needed under the cell diagonally below before a particle
+    //slides off a pile, higher values pile up more steeply
+    pub repose: usize,
+    pub behavior: Behavior,
+    //Only used by explosive materials
+    pub blast: Blast,
+    //Energy an explosion ray loses passing through the material
+    pub hardness: f32,
+    pub electricity: Electricity,
+    pub transforms: Vec<NeighborTransform>,
+    //Temperature when placed, heat sources stay at this temperature
+    pub temperature: f32,
+    pub heat_source: bool,
+    //How quickly heat moves in and out of the material, between 0 and 1
+    pub conductivity: f32,
+    //Fraction of the difference to the ambient temperature lost each step
+    pub cooling: f32,
+    pub below: Option<PhaseChange>,
+    pub above: Option<PhaseChange>,
+    pub decay: Option<Decay>,
+    pub burn_out: Option<BurnOut>,
+    pub growth: Option<Growth>,
+    pub germination: Option<Germination>,
+    pub emit: Option<Emit>,
+    pub burn: Option<Burn>,
+}
 
-            let sand_property =
-                SandProperties::from_vecs(Some(can_replace), Some(replace_with), Some(can_sink_in));
-            sand_sim_properties.add_sand_property(Sand::Lava, sand_property);
+impl SandProperties {
+    pub fn empty() -> Self {
+        SandProperties {
+            can_replace: MaterialSet::default(),
+            replace_with: MaterialMap::default(),
+            density: 1000.0,
+            sink_into: MaterialMap::default(),
+            rise_into: MaterialMap::default(),
+            movement: Movement::Static,
+            repose: 1,
+            behavior: Behavior::None,
+            blast: Blast::default(),
+            hardness: 1.0,
+            electricity: Electricity::None,
+            transforms: Vec::new(),
+            temperature: AMBIENT_TEMPERATURE,
+            heat_source: false,
+            conductivity: 0.0,
+            cooling: 0.0,
+            below: None,
+            above: None,
+            decay: None,
+            burn_out: None,
+            growth: None,
+            germination: None,
+            emit: None,
+            burn: None,
         }
+    }
 
-        //Stone
-        {
-            let can_sink_in = vec![Sand::Oil, Sand::Water, Sand::Acid];
-            let replace_with = vec![(Sand::Acid, Sand::Acid)];
-
-            let sand_property =
-                SandProperties::from_vecs(None, Some(replace_with), Some(can_sink_in));
-            sand_sim_properties.add_sand_property(Sand::Stone, sand_property);
-        }
+    pub fn from_vecs(
+        can_replace: Option<Vec<MaterialId>>,
+        replace_with: Option<Vec<(MaterialId, MaterialId)>>,
+    ) -> Self {
+        let mut properties = Self::empty();
 
-        //Explosive
-        {
-            let can_replace = vec![Sand::Fire];
-            let can_sink_in = vec![Sand::Oil, Sand::Water, Sand::Acid];
+        properties.add_replaceable(MaterialId::AIR);
 
-            let sand_property =
-                SandProperties::from_vecs(Some(can_replace), None, Some(can_sink_in));
-            sand_sim_properties.add_sand_property(Sand::Explosive, sand_property);
+        if let Some(can_replace) = can_replace {
+            can_replace
+                .into_iter()
+                .for_each(|sand| properties.add_replaceable(sand));
         }
 
-        //Explosion
-        {
-            let can_replace = vec![
-                Sand::Water,
-                Sand::Wood,
-                Sand::Fire,
-                Sand::Sand,
-                Sand::Explosive,
-                Sand::Lava,
-                Sand::Oil,
-                Sand::Acid,
-            ];
-
-            let explosion_property = SandProperties::from_vecs(Some(can_replace), None, None);
-            sand_sim_properties.add_sand_property(Sand::Explosion, explosion_property);
+        if let Some(replace_with) = replace_with {
+            replace_with
+                .into_iter()
+                .for_each(|(sand1, sand2)| properties.add_replace_with(sand1, sand2));
         }
 
-        sand_sim_properties
+        properties
+    }
+
+    pub fn add_replaceable(&mut self, sand: MaterialId) {
+        self.can_replace.insert(sand);
+    }
+
+    pub fn add_replace_with(&mut self, can_replace: MaterialId, replace_with: MaterialId) {
+        self.replace_with.insert(can_replace, replace_with);
     }
 
-    pub fn get_sand_property(&self, s: Sand) -> Option<&SandProperties> {
-        self.0.get(&s)
+    pub fn is_fluid(&self) -> bool {
+        matches!(self.movement, Movement::Liquid | Movement::Gas)
     }
 
-    pub fn add_sand_property(&mut self, s: Sand, properties: SandProperties) {
-        self.0.insert(s, properties);
+    pub fn replace(&self, sand: MaterialId, sand_to_replace: MaterialId) -> MaterialId {
+        match self.replace_with.get(sand_to_replace) {
+            Some(s) => s,
+            _ => sand,
+        }
     }
 }
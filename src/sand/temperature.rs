@@ -0,0 +1,241 @@
+//Heat conduction between cells and the phase changes it causes
+
+use super::{
+    material_registry::{MaterialId, MaterialRegistry},
+    sand_properties::{Behavior, Blast, PhaseChange},
+    update_sand, SandGrid,
+};
+
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+//Temperatures this close to the ambient temperature are rounded to it
+//so that cold regions of the grid settle down completely
+const SETTLE_EPSILON: f32 = 0.05;
+
+//Thermal properties of every material indexed by id, built once per step
+//so the inner loop does not need to look up the properties of each cell
+struct ThermalTable {
+    conductivity: [f32; 256],
+    cooling: [f32; 256],
+    heat_source: [Option<f32>; 256],
+    below: [Option<PhaseChange>; 256],
+    above: [Option<PhaseChange>; 256],
+    //Explosives blow up instead of changing when they get too hot
+    blast: [Option<Blast>; 256],
+}
+
+impl ThermalTable {
+    fn new(registry: &MaterialRegistry) -> Self {
+        let mut table = Self {
+            conductivity: [0.0; 256],
+            cooling: [0.0; 256],
+            heat_source: [None; 256],
+            below: [None; 256],
+            above: [None; 256],
+            blast: [None; 256],
+        };
+
+        for id in registry.ids() {
+            if let Some(properties) = registry.get_sand_property(id) {
+                let i = id.0 as usize;
+                table.conductivity[i] = properties.conductivity;
+                table.cooling[i] = properties.cooling;
+                if properties.heat_source {
+                    table.heat_source[i] = Some(properties.temperature);
+                }
+                table.below[i] = properties.below;
+                table.above[i] = properties.above;
+                if properties.behavior == Behavior::Explosive {
+                    table.blast[i] = Some(properties.blast);
+                }
+            }
+        }
+
+        table
+    }
+}
+
+impl SandGrid {
+    pub fn get_temperature(&self, x: usize, y: usize) -> f32 {
+        if self.out_of_bounds(x as isize, y as isize) {
+            return AMBIENT_TEMPERATURE;
+        }
+
+        self.temperature[y * self.width + x]
+    }
+
+    pub fn set_temperature(&mut self, x: usize, y: usize, temperature: f32) {
+        if self.out_of_bounds(x as isize, y as isize) {
+            return;
+        }
+
+        self.temperature[y * self.width + x] = temperature;
+        self.chunks.mark_hot(x, y);
+    }
+
+    pub(crate) fn swap_temperature(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
+        if self.out_of_bounds(x1 as isize, y1 as isize)
+            || self.out_of_bounds(x2 as isize, y2 as isize)
+        {
+            return;
+        }
+
+        let (i, j) = (y1 * self.width + x1, y2 * self.width + x2);
+        if self.temperature[i] == self.temperature[j] {
+            return;
+        }
+
+        self.temperature.swap(i, j);
+        self.chunks.mark_hot(x1, y1);
+        self.chunks.mark_hot(x2, y2);
+    }
+
+    //Heat flows between the 4 neighbors of every cell, the amount depends
+    //on the lower conductivity of the two materials. Materials that get
+    //too hot or too cold change into other materials afterwards. Chunks
+    //where nothing can change are skipped.
+    pub(crate) fn update_temperature(&mut self, registry: &MaterialRegistry) {
+        let table = ThermalTable::new(registry);
+        let heat_active = self.chunks.heat_active();
+        let active: Vec<usize> = (0..heat_active.len()).filter(|i| heat_active[*i]).collect();
+
+        let mut next = std::mem::take(&mut self.temperature_buffer);
+        next.resize(self.temperature.len(), AMBIENT_TEMPERATURE);
+
+        for &chunk in &active {
+            let bounds = self.chunks.bounds(chunk);
+            let mut hot = false;
+
+            for y in bounds.min_y..=bounds.max_y {
+                for x in bounds.min_x..=bounds.max_x {
+                    let i = y * self.width + x;
+                    let id = self.grid[i].sand_type.0 as usize;
+
+                    if let Some(source_temperature) = table.heat_source[id] {
+                        next[i] = source_temperature;
+                        hot = true;
+                        continue;
+                    }
+
+                    let temperature = self.temperature[i];
+                    let conductivity = table.conductivity[id];
+                    let mut change = 0.0;
+                    let neighbors = [
+                        (x > 0).then(|| i - 1),
+                        (x + 1 < self.width).then(|| i + 1),
+                        (y > 0).then(|| i - self.width),
+                        (y + 1 < self.height).then(|| i + self.width),
+                    ];
+                    for neighbor in neighbors.into_iter().flatten() {
+                        let neighbor_id = self.grid[neighbor].sand_type.0 as usize;
+                        let k = conductivity.min(table.conductivity[neighbor_id]) * 0.25;
+                        change += k * (self.temperature[neighbor] - temperature);
+                    }
+
+                    let mut new_temperature = temperature + change;
+                    new_temperature -= (new_temperature - AMBIENT_TEMPERATURE) * table.cooling[id];
+                    if (new_temperature - AMBIENT_TEMPERATURE).abs() < SETTLE_EPSILON {
+                        new_temperature = AMBIENT_TEMPERATURE;
+                    }
+                    next[i] = new_temperature;
+                    hot |= new_temperature != AMBIENT_TEMPERATURE;
+                }
+            }
+
+            self.chunks.hot[chunk] = hot;
+        }
+
+        //The new temperatures are only copied back once every chunk has
+        //been done so that heat moves the same way in every direction
+        for &chunk in &active {
+            let bounds = self.chunks.bounds(chunk);
+            for y in bounds.min_y..=bounds.max_y {
+                let row = (y * self.width + bounds.min_x)..=(y * self.width + bounds.max_x);
+                self.temperature[row.clone()].copy_from_slice(&next[row]);
+            }
+        }
+        self.temperature_buffer = next;
+
+        //Materials that moved into a chunk at the ambient temperature
+        //can also change if they are unstable at that temperature
+        let changing: Vec<usize> = (0..heat_active.len())
+            .filter(|i| heat_active[*i] || !self.chunks.dirty[*i].is_empty())
+            .collect();
+        for chunk in changing {
+            let bounds = self.chunks.bounds(chunk);
+            for y in bounds.min_y..=bounds.max_y {
+                for x in bounds.min_x..=bounds.max_x {
+                    let i = y * self.width + x;
+                    let id = self.grid[i].sand_type.0 as usize;
+                    let temperature = self.temperature[i];
+
+                    let phase_change = match (table.below[id], table.above[id]) {
+                        (Some(below), _) if temperature < below.temperature => below,
+                        (_, Some(above)) if temperature > above.temperature => {
+                            if let (Some(blast), Some(explosion_property)) = (
+                                table.blast[id],
+                                registry.get_sand_property(MaterialId::EXPLOSION),
+                            ) {
+                                update_sand::detonate(
+                                    x,
+                                    y,
+                                    self,
+                                    explosion_property,
+                                    &blast,
+                                    registry,
+                                );
+                                continue;
+                            }
+                            above
+                        }
+                        _ => continue,
+                    };
+
+                    self.set_sand(x, y, phase_change.turn_into);
+                    self.set_updated(x, y);
+                    self.set_can_update(x, y);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sand::brush::BrushFilter;
+
+    fn count(grid: &SandGrid, sand: MaterialId) -> usize {
+        (0..grid.height)
+            .flat_map(|y| (0..grid.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| grid.get_sand(x, y) == sand)
+            .count()
+    }
+
+    //Hot stone that is not burning heats the explosive next to it
+    //until it goes off, which blows away the sand next to it
+    #[test]
+    fn heat_detonates_explosive() {
+        let registry = MaterialRegistry::builtin();
+        let filter = BrushFilter::default();
+        let mut grid = SandGrid::with_seed(64, 64, 1);
+
+        let floor: Vec<(i32, i32)> = (0..64).map(|x| (x, 41)).collect();
+        let heap: Vec<(i32, i32)> = (36..41)
+            .flat_map(|y| (26..34).map(move |x| (x, y)))
+            .collect();
+        grid.place_cells(MaterialId::WALL, &floor, &filter, &registry);
+        grid.place_cells(MaterialId::STONE, &[(19, 40)], &filter, &registry);
+        grid.place_cells(MaterialId::EXPLOSIVE, &[(20, 40)], &filter, &registry);
+        grid.place_cells(MaterialId::SAND, &heap, &filter, &registry);
+        grid.set_temperature(19, 40, 1000.0);
+
+        let mut frame = 0;
+        while grid.get_sand(20, 40) == MaterialId::EXPLOSIVE {
+            assert!(frame < 100, "the explosive never got hot enough");
+            grid.update_sand(&registry, frame);
+            frame += 1;
+        }
+
+        assert_eq!(count(&grid, MaterialId::SAND), 0);
+    }
+}
@@ -1,10 +1,14 @@
 use super::{
+    material_registry::{MaterialId, MaterialRegistry},
     sand_physics,
-    sand_properties::{SandProperties, SandSimulationProperties},
-    Sand, SandGrid,
+    sand_properties::{
+        Behavior, Blast, Burn, Decay, Emit, Germination, GrowDirection, Growth, Movement,
+        NeighborTransform, SandProperties,
+    },
+    SandGrid,
 };
 
-fn count_neighbors(x: usize, y: usize, sand_grid: &SandGrid, sand: Sand) -> u32 {
+fn count_neighbors(x: usize, y: usize, sand_grid: &SandGrid, sand: MaterialId) -> u32 {
     const NEIGHBOR_X: [isize; 4] = [-1, 1, 0, 0];
     const NEIGHBOR_Y: [isize; 4] = [0, 0, -1, 1];
 
@@ -52,7 +56,7 @@ pub fn update_liquid(x: usize, y: usize, sand_grid: &mut SandGrid, properties: &
         return;
     }
 
-    if rand::random() {
+    if sand_grid.random() {
         if sand_physics::flow_left_right(x, y, sand_grid, properties) {
             return;
         }
@@ -71,59 +75,125 @@ pub fn update_liquid(x: usize, y: usize, sand_grid: &mut SandGrid, properties: &
     }
 }
 
+//Chance of a gas drifting sideways instead of rising
+const GAS_DRIFT: f64 = 0.3;
+
+#[allow(clippy::needless_return)]
+pub fn update_gas(x: usize, y: usize, sand_grid: &mut SandGrid, properties: &SandProperties) {
+    if sand_grid.random::<f64>() < GAS_DRIFT
+        && sand_physics::flow_left_right(x, y, sand_grid, properties)
+    {
+        return;
+    }
+
+    if sand_physics::rise_up(x, y, sand_grid, properties) {
+        return;
+    }
+
+    if sand_physics::rise_left_right(x, y, sand_grid, properties) {
+        return;
+    }
+
+    if sand_physics::flow_left_right(x, y, sand_grid, properties) {
+        return;
+    }
+}
+
+//The new material starts at its own temperature so that
+//condensed steam does not boil again straight away
+pub fn update_decay(
+    x: usize,
+    y: usize,
+    sand_grid: &mut SandGrid,
+    decay: &Decay,
+    registry: &MaterialRegistry,
+) {
+    if sand_grid.random::<f64>() * (decay.lifetime as f64) < 1.0 {
+        sand_grid.set_sand(x, y, decay.turn_into);
+        sand_grid.set_temperature(x, y, registry.initial_temperature(decay.turn_into));
+        sand_grid.set_updated(x, y);
+    }
+}
+
+//Rays lose the hardness of every material they pass through and stop
+//when they run out of energy, materials explosions can not replace
+//are left standing but still slow the ray down
 pub fn cast_ray(
     x: usize,
     y: usize,
     angle: f64,
     sand_grid: &mut SandGrid,
     properties: &SandProperties,
-    radius: usize,
+    blast: &Blast,
+    registry: &MaterialRegistry,
 ) {
-    let mut posx = 0.0f64;
-    let mut posy = 0.0f64;
-    while (posx * posx + posy * posy).sqrt() < radius as f64 {
-        posx += angle.cos() * 2.0;
-        posy += angle.sin() * 2.0;
-        let trans_x = posx + x as f64;
-        let trans_y = posy + y as f64;
-
-        if trans_x < 0.0
-            || trans_y < 0.0
-            || trans_x >= sand_grid.width as f64
-            || trans_y >= sand_grid.height as f64
-        {
+    //Walks the ray one cell at a time from the middle of the exploding
+    //cell so it can not step over a thin wall
+    let (dir_x, dir_y) = (angle.cos(), angle.sin());
+    let step_x = if dir_x < 0.0 { -1 } else { 1 };
+    let step_y = if dir_y < 0.0 { -1 } else { 1 };
+    //Distance along the ray between two borders in the same direction
+    let delta_x = (1.0 / dir_x).abs();
+    let delta_y = (1.0 / dir_y).abs();
+    //Distance along the ray to the next border
+    let mut next_x = delta_x * 0.5;
+    let mut next_y = delta_y * 0.5;
+    let mut trans_x = x as isize;
+    let mut trans_y = y as isize;
+    let mut energy = blast.energy;
+    loop {
+        let distance = if next_x < next_y {
+            trans_x += step_x;
+            next_x += delta_x;
+            next_x - delta_x
+        } else {
+            trans_y += step_y;
+            next_y += delta_y;
+            next_y - delta_y
+        };
+
+        if distance >= blast.radius as f64 || sand_grid.out_of_bounds(trans_x, trans_y) {
             return;
         }
 
-        let trans_x = trans_x.floor() as usize;
-        let trans_y = trans_y.floor() as usize;
+        let trans_x = trans_x as usize;
+        let trans_y = trans_y as usize;
+        let sand = sand_grid.get_sand(trans_x, trans_y);
 
-        if properties
-            .can_replace
-            .contains(&sand_grid.get_sand(trans_x, trans_y))
-        {
-            sand_grid.set_sand(trans_x, trans_y, Sand::Fire);
+        energy -= registry
+            .get_sand_property(sand)
+            .map_or(0.0, |sand_property| sand_property.hardness);
+        if energy <= 0.0 {
+            return;
+        }
+
+        if properties.can_replace.contains(sand) {
+            sand_grid.set_sand(trans_x, trans_y, MaterialId::FIRE);
             sand_grid.set_updated(trans_x, trans_y);
             sand_grid.set_can_update(trans_x, trans_y);
-        } else {
-            return;
         }
     }
 }
 
+//Rays are about this many cells apart where they end,
+//so smaller explosions cast fewer rays
+const RAY_SPACING: f64 = 3.2;
+
 pub fn explode(
     x: usize,
     y: usize,
     sand_grid: &mut SandGrid,
     properties: &SandProperties,
-    radius: usize,
+    blast: &Blast,
+    registry: &MaterialRegistry,
 ) {
-    sand_grid.set_sand(x, y, Sand::Fire);
+    sand_grid.set_sand(x, y, MaterialId::FIRE);
 
+    let step = (RAY_SPACING / blast.radius as f64).min(0.5);
     let mut angle = 0.0f64;
     while angle < std::f64::consts::PI * 2.0 {
-        cast_ray(x, y, angle, sand_grid, properties, radius);
-        angle += 0.05;
+        cast_ray(x, y, angle, sand_grid, properties, blast, registry);
+        angle += step;
     }
 }
 
@@ -131,26 +201,39 @@ pub fn update_explosive(
     x: usize,
     y: usize,
     sand_grid: &mut SandGrid,
-    properties: &SandProperties,
-    sand_sim_properties: &SandSimulationProperties,
+    blast: &Blast,
+    registry: &MaterialRegistry,
 ) {
-    let explosion_property = match sand_sim_properties.get_sand_property(Sand::Explosion) {
+    let explosion_property = match registry.get_sand_property(MaterialId::EXPLOSION) {
         Some(sand_prop) => sand_prop,
         _ => return,
     };
 
     //If it is bordering lava or fire, then explode
-    if count_neighbors(x, y, sand_grid, Sand::Lava) >= 1
-        || count_neighbors(x, y, sand_grid, Sand::Fire) >= 1
+    if count_neighbors(x, y, sand_grid, MaterialId::LAVA) >= 1
+        || count_neighbors(x, y, sand_grid, MaterialId::FIRE) >= 1
     {
-        sand_grid.set_sand(x, y, Sand::Fire);
-        explode(x, y, sand_grid, explosion_property, 64);
-        return;
+        detonate(x, y, sand_grid, explosion_property, blast, registry);
     }
+}
+
+//Sets off an explosive, also used when one gets too hot
+pub fn detonate(
+    x: usize,
+    y: usize,
+    sand_grid: &mut SandGrid,
+    explosion_property: &SandProperties,
+    blast: &Blast,
+    registry: &MaterialRegistry,
+) {
+    sand_grid.set_sand(x, y, MaterialId::FIRE);
 
-    transform_from_neighbors(x, y, Sand::Lava, Sand::Fire, sand_grid, 1, 4, 1.0);
-    transform_from_neighbors(x, y, Sand::Fire, Sand::Fire, sand_grid, 1, 4, 1.0);
-    update_particle(x, y, sand_grid, properties);
+    //Explosions reach further than a chunk, when chunks are updated
+    //in parallel they happen after the chunks have been merged back
+    match &mut sand_grid.deferred_explosions {
+        Some(explosions) => explosions.push((x, y, *blast)),
+        _ => explode(x, y, sand_grid, explosion_property, blast, registry),
+    }
 }
 
 pub fn update_fire(x: usize, y: usize, sand_grid: &mut SandGrid, properties: &SandProperties) {
@@ -169,18 +252,17 @@ pub fn update_fire(x: usize, y: usize, sand_grid: &mut SandGrid, properties: &Sa
             let posx = (xoff + x as isize) as usize;
             let posy = (yoff + y as isize) as usize;
 
+            //Fire spreads to flammable materials by heating them up
             if properties
                 .can_replace
-                .contains(&sand_grid.get_sand(posx, posy))
-                && sand_grid.get_sand(posx, posy) != Sand::Air
+                .contains(sand_grid.get_sand(posx, posy))
+                && sand_grid.get_sand(posx, posy) != MaterialId::AIR
             {
-                if rand::random::<f64>().fract() < 0.01 {
-                    sand_grid.set_sand(posx, posy, Sand::Fire);
-                    sand_grid.set_updated(posx, posy);
-                }
                 flammable_count += 1;
-            } else if sand_grid.get_sand(posx, posy) == Sand::Air && rand::random::<f64>() < 0.065 {
-                sand_grid.set_sand(posx, posy, Sand::Fire);
+            } else if sand_grid.get_sand(posx, posy) == MaterialId::AIR
+                && sand_grid.random::<f64>() < 0.065
+            {
+                sand_grid.set_sand(posx, posy, MaterialId::FIRE);
                 sand_grid.set_updated(posx, posy);
                 sand_grid.set_can_update(posx, posy);
             }
@@ -188,35 +270,370 @@ pub fn update_fire(x: usize, y: usize, sand_grid: &mut SandGrid, properties: &Sa
     }
 
     if !((flammable_count >= 1)
-        || (count_neighbors(x, y, sand_grid, Sand::Fire) >= 2 && rand::random::<f64>() < 0.8))
+        || (count_neighbors(x, y, sand_grid, MaterialId::FIRE) >= 2
+            && sand_grid.random::<f64>() < 0.8))
     {
-        sand_grid.set_sand(x, y, Sand::Air);
+        let burnt = match properties.burn_out {
+            Some(burn_out) if sand_grid.random::<f64>() < burn_out.probability => {
+                burn_out.turn_into
+            }
+            _ => MaterialId::AIR,
+        };
+        sand_grid.set_sand(x, y, burnt);
         sand_grid.set_updated(x, y);
     }
 }
 
+//Whether the cell at the position has the right number
+//of neighbors for a transform to happen
+pub fn can_transform(
+    x: usize,
+    y: usize,
+    sand_grid: &SandGrid,
+    transform: &NeighborTransform,
+) -> bool {
+    let count = count_neighbors(x, y, sand_grid, transform.neighbor);
+    count >= transform.min_count && count <= transform.max_count
+}
+
 //Transforms the cell at the position based on the number
 //of neighboring cells of a certain type
-#[allow(clippy::too_many_arguments)]
 pub fn transform_from_neighbors(
     x: usize,
     y: usize,
-    neighbor: Sand,
-    turn_into: Sand,
     sand_grid: &mut SandGrid,
-    min_count: u32,
-    max_count: u32,
-    probability: f64,
+    transform: &NeighborTransform,
 ) {
     if sand_grid.get_updated(x, y) {
         return;
     }
 
-    if count_neighbors(x, y, sand_grid, neighbor) >= min_count
-        && count_neighbors(x, y, sand_grid, neighbor) <= max_count
-        && rand::random::<f64>() < probability
+    if can_transform(x, y, sand_grid, transform)
+        && sand_grid.random::<f64>() < transform.probability
     {
-        sand_grid.set_sand(x, y, turn_into);
+        sand_grid.set_sand(x, y, transform.turn_into);
         sand_grid.set_updated(x, y);
+        //The neighbors might be able to transform now, like
+        //water freezing next to water that just froze
+        sand_grid.set_adjacent_can_update(x, y);
+    }
+}
+
+const SIDES: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+//The cells on the 4 sides of the position that are inside the grid
+fn sides(x: usize, y: usize, sand_grid: &SandGrid) -> impl Iterator<Item = (usize, usize)> + '_ {
+    SIDES
+        .iter()
+        .map(move |(dx, dy)| (x as isize + dx, y as isize + dy))
+        .filter(|&(nx, ny)| !sand_grid.out_of_bounds(nx, ny))
+        .map(|(nx, ny)| (nx as usize, ny as usize))
+}
+
+//A side of the cell with a certain material
+fn side_with(x: usize, y: usize, sand_grid: &SandGrid, sand: MaterialId) -> Option<(usize, usize)> {
+    sides(x, y, sand_grid).find(|&(nx, ny)| sand_grid.get_sand(nx, ny) == sand)
+}
+
+//An air cell growth can spread into, with the cell of
+//the material it needs next to it
+type GrowthTarget = ((usize, usize), Option<(usize, usize)>);
+
+//The air cells growth can spread into from the position
+fn growth_targets(
+    x: usize,
+    y: usize,
+    sand_grid: &SandGrid,
+    growth: &Growth,
+) -> [Option<GrowthTarget>; 4] {
+    let mut targets = [None; 4];
+
+    for (i, (dx, dy)) in SIDES.iter().enumerate() {
+        if growth.direction == GrowDirection::Down {
+            //Only hanging from something, like a ceiling or more of itself
+            if *dy != 1 || (y > 0 && sand_grid.get_sand(x, y - 1) == MaterialId::AIR) {
+                continue;
+            }
+        }
+
+        let nx = x as isize + dx;
+        let ny = y as isize + dy;
+        if sand_grid.out_of_bounds(nx, ny)
+            || sand_grid.get_sand(nx as usize, ny as usize) != MaterialId::AIR
+        {
+            continue;
+        }
+
+        let target = (nx as usize, ny as usize);
+        match growth.needs {
+            Some(needs) => {
+                if let Some(needed) = side_with(target.0, target.1, sand_grid, needs) {
+                    targets[i] = Some((target, Some(needed)));
+                }
+            }
+            None => targets[i] = Some((target, None)),
+        }
+    }
+
+    targets
+}
+
+pub fn can_grow(x: usize, y: usize, sand_grid: &SandGrid, growth: &Growth) -> bool {
+    growth_targets(x, y, sand_grid, growth)
+        .iter()
+        .any(|target| target.is_some())
+}
+
+//Spreads the material at the position into a random neighboring
+//air cell, using up the material that cell needed
+pub fn update_growth(
+    x: usize,
+    y: usize,
+    sand_grid: &mut SandGrid,
+    growth: &Growth,
+    registry: &MaterialRegistry,
+) {
+    if sand_grid.get_updated(x, y) || sand_grid.random::<f64>() >= growth.probability {
+        return;
+    }
+
+    let targets = growth_targets(x, y, sand_grid, growth);
+    let count = targets.iter().flatten().count();
+    if count == 0 {
+        return;
+    }
+
+    let pick = (sand_grid.random::<f64>() * count as f64) as usize;
+    let Some(&((tx, ty), needed)) = targets.iter().flatten().nth(pick.min(count - 1)) else {
+        return;
+    };
+
+    let sand = sand_grid.get_sand(x, y);
+    sand_grid.set_sand(tx, ty, sand);
+    sand_grid.set_temperature(tx, ty, registry.initial_temperature(sand));
+    sand_grid.set_updated(tx, ty);
+    sand_grid.set_adjacent_can_update(tx, ty);
+
+    if let (true, Some((nx, ny))) = (growth.consumes, needed) {
+        sand_grid.set_sand(nx, ny, MaterialId::AIR);
+        sand_grid.set_adjacent_can_update(nx, ny);
+    }
+}
+
+//Resting on germination.on while that touches germination.near,
+//including diagonally
+pub fn can_germinate(x: usize, y: usize, sand_grid: &SandGrid, germination: &Germination) -> bool {
+    if y + 1 >= sand_grid.height || sand_grid.get_sand(x, y + 1) != germination.on {
+        return false;
+    }
+
+    let below = y as isize + 1;
+    (below - 1..=below + 1).any(|ny| {
+        (x as isize - 1..=x as isize + 1).any(|nx| {
+            !sand_grid.out_of_bounds(nx, ny)
+                && sand_grid.get_sand(nx as usize, ny as usize) == germination.near
+        })
+    })
+}
+
+pub fn update_germination(
+    x: usize,
+    y: usize,
+    sand_grid: &mut SandGrid,
+    germination: &Germination,
+    registry: &MaterialRegistry,
+) {
+    if sand_grid.get_updated(x, y) {
+        return;
+    }
+
+    if can_germinate(x, y, sand_grid, germination)
+        && sand_grid.random::<f64>() < germination.probability
+    {
+        sand_grid.set_sand(x, y, germination.turn_into);
+        sand_grid.set_temperature(x, y, registry.initial_temperature(germination.turn_into));
+        sand_grid.set_updated(x, y);
+        sand_grid.set_adjacent_can_update(x, y);
+    }
+}
+
+//Places sand in every empty cell on the 4 sides of the position, the new
+//particles are marked as updated so they start moving on the next step
+fn fill_empty_sides(
+    x: usize,
+    y: usize,
+    sand_grid: &mut SandGrid,
+    sand: MaterialId,
+    registry: &MaterialRegistry,
+) {
+    let temperature = registry.initial_temperature(sand);
+    let empty: Vec<(usize, usize)> = sides(x, y, sand_grid)
+        .filter(|&(nx, ny)| sand_grid.get_sand(nx, ny) == MaterialId::AIR)
+        .collect();
+
+    for (nx, ny) in empty {
+        sand_grid.set_sand(nx, ny, sand);
+        sand_grid.set_temperature(nx, ny, temperature);
+        sand_grid.set_updated(nx, ny);
+    }
+}
+
+fn has_empty_side(x: usize, y: usize, sand_grid: &SandGrid) -> bool {
+    side_with(x, y, sand_grid, MaterialId::AIR).is_some()
+}
+
+//The first material touching the position that a cloner can copy,
+//cloners, voids and materials that emit are never copied
+fn clone_source(
+    x: usize,
+    y: usize,
+    sand_grid: &SandGrid,
+    registry: &MaterialRegistry,
+) -> Option<MaterialId> {
+    sides(x, y, sand_grid)
+        .map(|(nx, ny)| sand_grid.get_sand(nx, ny))
+        .find(|&sand| {
+            sand != MaterialId::AIR
+                && registry.get_sand_property(sand).is_some_and(|properties| {
+                    !matches!(properties.behavior, Behavior::Cloner | Behavior::Void)
+                        && properties.emit.is_none()
+                })
+        })
+}
+
+//The first material to touch a cloner is remembered and
+//is the only one it copies from then on
+pub fn update_cloner(x: usize, y: usize, sand_grid: &mut SandGrid, registry: &MaterialRegistry) {
+    let i = y * sand_grid.width + x;
+    if sand_grid.cloned[i] == MaterialId::AIR {
+        match clone_source(x, y, sand_grid, registry) {
+            Some(sand) => sand_grid.cloned[i] = sand,
+            None => return,
+        }
+    }
+
+    fill_empty_sides(x, y, sand_grid, sand_grid.cloned[i], registry);
+}
+
+pub fn update_emit(
+    x: usize,
+    y: usize,
+    sand_grid: &mut SandGrid,
+    emit: &Emit,
+    registry: &MaterialRegistry,
+) {
+    if sand_grid.frame.is_multiple_of(emit.interval) {
+        fill_empty_sides(x, y, sand_grid, emit.sand, registry);
+    }
+}
+
+//Whether a particle is next to a void in a direction it moves in,
+//it falls, flows or drifts into the void and is deleted. Particles
+//resting beside or on top of a void they can not move into are left alone.
+pub fn enters_void(
+    x: usize,
+    y: usize,
+    sand_grid: &SandGrid,
+    movement: Movement,
+    registry: &MaterialRegistry,
+) -> bool {
+    let directions: &[(isize, isize)] = match movement {
+        Movement::Static => &[],
+        Movement::Powder | Movement::Fall => &[(0, 1)],
+        Movement::Liquid => &[(0, 1), (-1, 0), (1, 0)],
+        Movement::Gas => &[(0, -1), (-1, 0), (1, 0)],
+    };
+
+    directions.iter().any(|(dx, dy)| {
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        !sand_grid.out_of_bounds(nx, ny)
+            && registry
+                .get_sand_property(sand_grid.get_sand(nx as usize, ny as usize))
+                .is_some_and(|properties| properties.behavior == Behavior::Void)
+    })
+}
+
+//Every interval steps the neighboring cells the burn spreads to catch,
+//they are marked as updated so the burn only moves one cell at a time
+pub fn update_burn(
+    x: usize,
+    y: usize,
+    sand_grid: &mut SandGrid,
+    burn: &Burn,
+    registry: &MaterialRegistry,
+) {
+    if sand_grid.get_updated(x, y) || !sand_grid.frame.is_multiple_of(burn.interval) {
+        return;
+    }
+
+    let sand = sand_grid.get_sand(x, y);
+    let catching: Vec<(usize, usize)> = sides(x, y, sand_grid)
+        .filter(|&(nx, ny)| sand_grid.get_sand(nx, ny) == burn.spreads_to)
+        .collect();
+
+    for (nx, ny) in catching {
+        sand_grid.set_sand(nx, ny, sand);
+        sand_grid.set_temperature(nx, ny, registry.initial_temperature(sand));
+        sand_grid.set_updated(nx, ny);
+    }
+
+    sand_grid.set_sand(x, y, burn.turn_into);
+    sand_grid.set_temperature(x, y, registry.initial_temperature(burn.turn_into));
+    sand_grid.set_updated(x, y);
+}
+
+//Cloners and materials that emit only sleep while there is nothing
+//for them to do, burning materials never sleep
+pub fn source_or_sink_active(
+    x: usize,
+    y: usize,
+    sand_grid: &SandGrid,
+    properties: &SandProperties,
+    registry: &MaterialRegistry,
+) -> bool {
+    let active = match properties.behavior {
+        Behavior::Cloner => {
+            has_empty_side(x, y, sand_grid)
+                && (sand_grid.cloned[y * sand_grid.width + x] != MaterialId::AIR
+                    || clone_source(x, y, sand_grid, registry).is_some())
+        }
+        _ => false,
+    };
+
+    active
+        || properties.burn.is_some()
+        || (properties.emit.is_some() && has_empty_side(x, y, sand_grid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sand::brush::BrushFilter;
+
+    #[test]
+    fn void_deletes_what_moves_into_it() {
+        let registry = MaterialRegistry::builtin();
+        let id = |name: &str| registry.id(name).unwrap();
+        let filter = BrushFilter::default();
+        let mut grid = SandGrid::with_seed(8, 8, 1);
+        let floor: Vec<(i32, i32)> = (0..8).map(|x| (x, 7)).collect();
+        grid.place_cells(MaterialId::WALL, &floor, &filter, &registry);
+        grid.place_cells(id("Void"), &[(3, 6)], &filter, &registry);
+        grid.place_cells(MaterialId::SAND, &[(2, 6), (3, 2)], &filter, &registry);
+
+        for frame in 0..20 {
+            grid.update_sand(&registry, frame);
+        }
+
+        //The sand beside the void stays, the sand that fell onto it is gone
+        assert_eq!(grid.get_sand(2, 6), MaterialId::SAND);
+        assert_eq!(grid.get_sand(3, 5), MaterialId::AIR);
+        assert_eq!(grid.get_sand(3, 6), id("Void"));
+        let sand = grid
+            .grid
+            .iter()
+            .filter(|particle| particle.sand_type == MaterialId::SAND)
+            .count();
+        assert_eq!(sand, 1);
     }
 }